@@ -0,0 +1,181 @@
+use crate::shell_words::{self, Dialect};
+use crate::types::{CompleteParams, CompleteResult, CompletionValue, Error};
+use clap::Args;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug, Args)]
+pub struct ArgcompleteArgs {
+    /// Complete the scripts without the `PYTHON_ARGCOMPLETE_OK` marker (e.g.
+    /// console scripts installed by pip).
+    #[arg(long)]
+    no_marker_check: bool,
+}
+
+pub fn run_argcomplete(args: ArgcompleteArgs) -> anyhow::Result<()> {
+    crate::server::serve(|params| handle_complete_request(&args, params))
+}
+
+/// The separator of the values.
+const IFS: char = '\x0b';
+/// The separator of the value and its description.
+const DFS: char = '\t';
+/// The marker of the scripts that support argcomplete, looked for in the
+/// beginning of the script.
+const MARKER: &[u8] = b"PYTHON_ARGCOMPLETE_OK";
+
+/// Starts the script with its fd 8 redirected to stdout, where argcomplete
+/// writes the completions (the real stdout is dropped).
+const LAUNCHER: &str = r#"exec "$0" 8>&1 >/dev/null"#;
+
+fn handle_complete_request(
+    args: &ArgcompleteArgs,
+    params: CompleteParams,
+) -> Result<CompleteResult, Error> {
+    if params.args.is_empty() {
+        return Err(Error::invalid_request(
+            "params.args is empty, required at least one element",
+        ));
+    }
+    let program = &params.args[0];
+    if !args.no_marker_check {
+        // Scripts without the marker would be run with the real args.
+        let path = params
+            .find_program(program)
+            .ok_or_else(|| Error::invalid_request(format!("{program} not found")))?;
+        if !has_marker(&path) {
+            return Err(Error::invalid_request(format!(
+                "{} is not an argcomplete script (PYTHON_ARGCOMPLETE_OK marker not found)",
+                path.display()
+            )));
+        }
+    }
+
+    let line: Vec<_> = params
+        .args
+        .iter()
+        .map(|a| shell_words::quote(a, Dialect::Bash, None))
+        .collect();
+    let line = line.join(" ");
+    let mut command = Command::new("sh");
+    command.args(["-c", LAUNCHER, program]);
+    params.apply_to(&mut command);
+    command
+        .env("_ARGCOMPLETE", "1")
+        // Fish is the shell, for which argcomplete does not escape the values.
+        .env("_ARGCOMPLETE_SHELL", "fish")
+        .env("_ARGCOMPLETE_SUPPRESS_SPACE", "1")
+        .env("_ARGCOMPLETE_IFS", IFS.to_string())
+        .env("_ARGCOMPLETE_DFS", DFS.to_string())
+        .env("COMP_POINT", line.len().to_string())
+        .env("COMP_LINE", line)
+        .env("COMP_TYPE", "9");
+    let output = command
+        .output()
+        .map_err(|e| Error::internal(format!("failed to run {program}: {e}")))?;
+    if !output.status.success() {
+        return Err(Error::internal(format!(
+            "{program} completion failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(parse_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_output(output: &str) -> CompleteResult {
+    CompleteResult {
+        values: output
+            .split(IFS)
+            .filter(|c| !c.is_empty())
+            .map(|c| {
+                let (value, description) = match c.split_once(DFS) {
+                    Some((value, description)) => (value, Some(description)),
+                    None => (c, None),
+                };
+                CompletionValue {
+                    // The continuation characters of argcomplete.
+                    no_space: value.ends_with(['=', '/', ':']),
+                    value: value.to_string(),
+                    description: description.filter(|d| !d.is_empty()).map(|d| d.to_string()),
+                    ..Default::default()
+                }
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Checks if the marker is in the first 1024 bytes of the file, like the
+/// global completion of argcomplete.
+fn has_marker(path: &Path) -> bool {
+    let mut head = Vec::with_capacity(1024);
+    let read = std::fs::File::open(path).and_then(|f| f.take(1024).read_to_end(&mut head));
+    read.is_ok() && head.windows(MARKER.len()).any(|w| w == MARKER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use googletest::prelude::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[gtest]
+    fn completes_with_fd_8() {
+        let dir = TempDir::new("argcomplete");
+        // Mimics argcomplete, writing the environment it gets to the fd 8.
+        let script = dir.join("mytool");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n# PYTHON_ARGCOMPLETE_OK\necho ignored\nprintf '%s\\t%s\\v--out=\\v' \"$COMP_POINT\" \"$COMP_LINE\" >&8\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let other = dir.join("other");
+        std::fs::write(&other, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&other, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let args = ArgcompleteArgs {
+            no_marker_check: false,
+        };
+        let params = |program: &str, arg: &str| CompleteParams {
+            args: vec![program.into(), arg.into(), "".into()],
+            working_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let result = handle_complete_request(&args, params("./mytool", "my file")).unwrap();
+        expect_that!(
+            result.values,
+            elements_are![
+                matches_pattern!(CompletionValue {
+                    value: eq("18"),
+                    description: some(eq(r"./mytool my\ file ")),
+                    no_space: eq(&false),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("--out="),
+                    description: none(),
+                    no_space: eq(&true),
+                }),
+            ]
+        );
+
+        // The point is a byte offset, like in bash.
+        let result = handle_complete_request(&args, params("./mytool", "café")).unwrap();
+        expect_that!(
+            result.values.first(),
+            some(matches_pattern!(CompletionValue {
+                value: eq("15"),
+                description: some(eq("./mytool café ")),
+            }))
+        );
+
+        expect_that!(
+            handle_complete_request(&args, params("./other", "my file")).is_err(),
+            eq(true)
+        );
+    }
+}
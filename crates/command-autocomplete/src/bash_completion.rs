@@ -0,0 +1,489 @@
+use crate::shell_words::{self, Dialect};
+use crate::system::{data_dirs, data_home};
+use crate::types::{CompleteParams, CompleteResult, CompletionValue, Error};
+use clap::Args;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Args)]
+pub struct BashCompletionArgs {
+    /// Additional directory with completion scripts (named after the
+    /// commands), searched before the bash-completion directories.
+    #[arg(long = "dir")]
+    dirs: Vec<PathBuf>,
+    /// The bash binary to use.
+    #[arg(long, default_value = "bash")]
+    bash: PathBuf,
+    /// How long to wait for the completions of a command, in milliseconds.
+    #[arg(long, default_value_t = 3000)]
+    timeout_ms: u64,
+}
+
+pub fn run_bash_completion(args: BashCompletionArgs) -> anyhow::Result<()> {
+    let mut session = BashSession::new(args);
+    crate::server::serve(|params| session.complete(params))
+}
+
+/// Defines the function that loads and invokes the completion function of a
+/// command (with the args of the completed command).
+///
+/// The output is NUL separated: the number of values, whether the space
+/// should be skipped after the values, followed by the values. When the
+/// working directory can't be entered, only `cd` is printed instead.
+///
+/// The job control puts the subshell of each request into its own process
+/// group, so that it can be killed with its children on timeout.
+const SCRIPT: &str = r#"
+set -m
+
+__cap_load() {
+    local cmd=$1 dir file
+    complete -p -- "$cmd" &>/dev/null && return
+    for dir in "${__cap_dirs[@]}"; do
+        for file in "$dir/$cmd" "$dir/$cmd.bash" "$dir/_$cmd"; do
+            if [[ -f $file ]]; then
+                . "$file" &>/dev/null
+                complete -p -- "$cmd" &>/dev/null && return
+            fi
+        done
+    done
+    if declare -F _comp_load &>/dev/null; then
+        _comp_load -- "$cmd" &>/dev/null
+    elif declare -F __load_completion &>/dev/null; then
+        __load_completion "$cmd" &>/dev/null
+    fi
+}
+
+# compopt works only inside of the real completion, so we record the options.
+compopt() {
+    while (($#)); do
+        case $1 in
+            -o) [[ $2 == nospace ]] && __cap_nospace=1; shift ;;
+            +o) [[ $2 == nospace ]] && __cap_nospace=0; shift ;;
+        esac
+        shift
+    done
+    return 0
+}
+
+__cap_complete() {
+    local cmd=${1##*/} spec func re='-F ([^ ]+)'
+    spec=$(complete -p -- "$cmd" 2>/dev/null)
+    [[ $spec =~ $re ]] && func=${BASH_REMATCH[1]}
+    __cap_nospace=0
+    [[ $spec == *"-o nospace"* ]] && __cap_nospace=1
+    COMPREPLY=()
+    if [[ -n $func ]]; then
+        COMP_WORDS=("$@")
+        COMP_CWORD=$((${#COMP_WORDS[@]} - 1))
+        COMP_LINE=$__cap_line
+        COMP_POINT=$__cap_point
+        COMP_TYPE=9
+        COMP_KEY=9
+        "$func" "$1" "${COMP_WORDS[COMP_CWORD]}" "${COMP_WORDS[COMP_CWORD-1]}" &>/dev/null </dev/null
+    fi
+    printf '%s\0' "${#COMPREPLY[@]}" "$__cap_nospace" "${COMPREPLY[@]}"
+}
+"#;
+
+/// A bash process, kept for the whole session, in which the completion
+/// scripts are loaded once.
+struct BashSession {
+    args: BashCompletionArgs,
+    bash: Option<Bash>,
+}
+
+struct Bash {
+    child: Child,
+    stdin: ChildStdin,
+    /// The NUL separated fields of the output, read by a thread so that the
+    /// reads can time out.
+    fields: Receiver<Vec<u8>>,
+    timeout: Duration,
+}
+
+impl BashSession {
+    fn new(args: BashCompletionArgs) -> Self {
+        BashSession { args, bash: None }
+    }
+
+    fn complete(&mut self, params: CompleteParams) -> Result<CompleteResult, Error> {
+        if params.args.is_empty() {
+            return Err(Error::invalid_request(
+                "params.args is empty, required at least one element",
+            ));
+        }
+        if self.bash.is_none() {
+            self.bash = Some(self.start()?);
+        }
+        let bash = self.bash.as_mut().expect("bash was just started");
+        let result = bash.complete(&params);
+        if result.as_ref().is_err_and(|e| e.code == "INTERNAL") {
+            // The process is in unknown state, so we start a new one for the
+            // next request.
+            if let Some(mut bash) = self.bash.take() {
+                let _ = bash.child.kill();
+                let _ = bash.child.wait();
+            }
+        }
+        result
+    }
+
+    fn start(&self) -> Result<Bash, Error> {
+        log::debug!("starting bash for completions");
+        let mut child = Command::new(&self.args.bash)
+            .args(["--norc", "--noprofile"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::internal(format!("failed to start bash: {e}")))?;
+        let stdin = child.stdin.take().ok_or_else(|| {
+            Error::internal("stdin missing in started process, this should never happen")
+        })?;
+        let stdout = child.stdout.take().ok_or_else(|| {
+            Error::internal("stdout missing in started process, this should never happen")
+        })?;
+        let (sender, fields) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut stdout = BufReader::new(stdout);
+            loop {
+                let mut field = vec![];
+                match stdout.read_until(b'\0', &mut field) {
+                    Ok(_) if field.pop() == Some(b'\0') => {
+                        if sender.send(field).is_err() {
+                            break;
+                        }
+                    }
+                    // The field is incomplete, bash has exited.
+                    Ok(_) => break,
+                    Err(err) => {
+                        log::warn!("failed to read from bash: {err}");
+                        break;
+                    }
+                }
+            }
+        });
+        let mut bash = Bash {
+            child,
+            stdin,
+            fields,
+            timeout: Duration::from_millis(self.args.timeout_ms),
+        };
+
+        let mut init = String::new();
+        let dirs: Vec<_> = self
+            .args
+            .dirs
+            .iter()
+            .map(|d| quote_path(d))
+            .chain(completion_dirs().iter().map(|d| quote_path(d)))
+            .collect();
+        init.push_str(&format!("__cap_dirs=({})\n", dirs.join(" ")));
+        if let Some(script) = main_script() {
+            init.push_str(&format!(". {} &>/dev/null\n", quote_path(&script)));
+        }
+        init.push_str(SCRIPT);
+        bash.write(&init)?;
+        Ok(bash)
+    }
+}
+
+impl Bash {
+    fn write(&mut self, script: &str) -> Result<(), Error> {
+        self.stdin
+            .write_all(script.as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| Error::internal(format!("failed to write to bash: {e}")))
+    }
+
+    fn read_field(&mut self, deadline: Instant) -> Result<String, Error> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.fields.recv_timeout(timeout) {
+            Ok(field) => Ok(String::from_utf8_lossy(&field).into_owned()),
+            Err(RecvTimeoutError::Timeout) => Err(Error::internal(format!(
+                "bash did not provide completions in {}ms",
+                self.timeout.as_millis()
+            ))),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(Error::internal("bash exited before providing completions"))
+            }
+        }
+    }
+
+    fn complete(&mut self, params: &CompleteParams) -> Result<CompleteResult, Error> {
+        let deadline = Instant::now() + self.timeout;
+        let quote = |s: &str| shell_words::quote(s, Dialect::Bash, None);
+        let command = Path::new(&params.args[0]).file_name().unwrap_or_default();
+        // The completion scripts are loaded in the session, so that they are
+        // loaded only once.
+        let mut script = format!("__cap_load {}\n", quote_raw(&command.to_string_lossy()));
+        // The rest runs in a subshell, so that the environment and the
+        // working directory of the request don't leak into the next ones.
+        script.push_str("(\nprintf '%s\\0' \"$BASHPID\"\n");
+        for env in &params.envs {
+            if is_exportable(&env.name) {
+                script.push_str(&format!("export {}={}\n", env.name, quote_raw(&env.value)));
+            }
+        }
+        if let Some(working_dir) = &params.working_dir {
+            script.push_str(&format!(
+                "cd -- {} 2>/dev/null || {{ printf 'cd\\0'; exit; }}\n",
+                quote_path(working_dir)
+            ));
+        }
+        let line: Vec<_> = params.args.iter().map(|a| quote(a)).collect();
+        let line = line.join(" ");
+        script.push_str(&format!("__cap_line={}\n", quote_raw(&line)));
+        // The point is a byte offset, which bash can't count in UTF-8 locales.
+        script.push_str(&format!("__cap_point={}\n", line.len()));
+        let args: Vec<_> = params.args.iter().map(|a| quote_raw(a)).collect();
+        script.push_str(&format!("__cap_complete {}\n)\n", args.join(" ")));
+        self.write(&script)?;
+
+        let pgid = self.read_field(deadline)?;
+        let result = self.read_result(params, deadline);
+        if result.as_ref().is_err_and(|e| e.code == "INTERNAL") {
+            kill_group(&pgid);
+        }
+        result
+    }
+
+    fn read_result(
+        &mut self,
+        params: &CompleteParams,
+        deadline: Instant,
+    ) -> Result<CompleteResult, Error> {
+        let count = self.read_field(deadline)?;
+        if count == "cd" {
+            return Err(Error::invalid_request(format!(
+                "failed to change the working directory to {}",
+                params
+                    .working_dir
+                    .as_deref()
+                    .unwrap_or(Path::new(""))
+                    .display()
+            )));
+        }
+        let count: usize = count
+            .parse()
+            .map_err(|e| Error::internal(format!("unexpected output from bash: {e}")))?;
+        let nospace = self.read_field(deadline)? == "1";
+        let mut values = Vec::with_capacity(count);
+        for _ in 0..count {
+            let value = self.read_field(deadline)?;
+            values.push(CompletionValue {
+                no_space: nospace || value.ends_with('/'),
+                value,
+                ..Default::default()
+            });
+        }
+        Ok(CompleteResult {
+            values,
+            ..Default::default()
+        })
+    }
+}
+
+/// Kills the subshell of a request and the processes it started.
+fn kill_group(pgid: &str) {
+    if pgid.parse::<u32>().is_err() {
+        return;
+    }
+    let status = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{pgid}")])
+        .stderr(Stdio::null())
+        .status();
+    if let Err(err) = status {
+        log::warn!("failed to kill the bash completion of group {pgid}: {err}");
+    }
+}
+
+/// Quotes the string, so that bash reads it back exactly (also when empty).
+fn quote_raw(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn quote_path(path: &Path) -> String {
+    quote_raw(&path.to_string_lossy())
+}
+
+/// Checks if the variable can be exported into the bash session.
+fn is_exportable(name: &str) -> bool {
+    const READONLY: &[&str] = &[
+        "BASHOPTS",
+        "EUID",
+        "PPID",
+        "SHELLOPTS",
+        "UID",
+        "PWD",
+        "OLDPWD",
+        "SHLVL",
+        "_",
+    ];
+    !name.is_empty()
+        && !name.starts_with("BASH")
+        && !name.starts_with("COMP_")
+        && !READONLY.contains(&name)
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// The directories with completion scripts, as searched by bash-completion.
+fn completion_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Ok(dir) = std::env::var("BASH_COMPLETION_USER_DIR") {
+        dirs.push(PathBuf::from(dir).join("completions"));
+    } else if let Some(data_home) = data_home() {
+        dirs.push(data_home.join("bash-completion/completions"));
+    }
+    for dir in data_dirs() {
+        dirs.push(dir.join("bash-completion/completions"));
+    }
+    dirs
+}
+
+/// The main script of bash-completion, that defines the helpers used by
+/// most of completion scripts.
+fn main_script() -> Option<PathBuf> {
+    data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("bash-completion/bash_completion"))
+        .chain([PathBuf::from("/etc/bash_completion")])
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::types::EnvironmentVariable;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn completes_with_local_completion_function() {
+        let dir = TempDir::new("bash-completion");
+        std::fs::write(
+            dir.join("mytool"),
+            r#"
+_mytool() {
+    local cur=$2
+    if [[ $COMP_CWORD == 1 ]]; then
+        COMPREPLY=($(compgen -W "build 'run it' test" -- "$cur"))
+    else
+        COMPREPLY=("$PWD" "${COMP_WORDS[1]}" "${MYVAR-unset}" "$COMP_POINT")
+        compopt -o nospace
+    fi
+}
+complete -F _mytool mytool
+"#,
+        )
+        .unwrap();
+        let mut session = BashSession::new(BashCompletionArgs {
+            dirs: vec![dir.to_path_buf()],
+            bash: "bash".into(),
+            timeout_ms: 3000,
+        });
+
+        let result = session
+            .complete(CompleteParams {
+                args: vec!["mytool".into(), "b".into()],
+                ..Default::default()
+            })
+            .unwrap();
+        expect_that!(
+            result.values,
+            elements_are![matches_pattern!(CompletionValue {
+                value: eq("build"),
+                no_space: eq(&false),
+            })]
+        );
+
+        // The second request is handled by the same bash process.
+        let result = session
+            .complete(CompleteParams {
+                args: vec!["/usr/bin/mytool".into(), "it's".into(), "".into()],
+                working_dir: Some(dir.to_path_buf()),
+                envs: vec![EnvironmentVariable {
+                    name: "MYVAR".into(),
+                    value: "set".into(),
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+        expect_that!(
+            result.values,
+            elements_are![
+                matches_pattern!(CompletionValue {
+                    value: eq(dir.to_str().unwrap()),
+                    no_space: eq(&true),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("it's"),
+                    no_space: eq(&true),
+                }),
+                matches_pattern!(CompletionValue { value: eq("set") }),
+                anything(),
+            ]
+        );
+
+        // The environment and the working directory are not kept.
+        let result = session
+            .complete(CompleteParams {
+                args: vec!["mytool".into(), "é".into(), "".into()],
+                ..Default::default()
+            })
+            .unwrap();
+        expect_that!(
+            result.values,
+            elements_are![
+                not(field!(CompletionValue.value, eq(dir.to_str().unwrap()))),
+                field!(CompletionValue.value, eq("é")),
+                field!(CompletionValue.value, eq("unset")),
+                // The point is in bytes, like in bash.
+                field!(CompletionValue.value, eq("10")),
+            ]
+        );
+
+        let result = session.complete(CompleteParams {
+            args: vec!["mytool".into(), "b".into()],
+            working_dir: Some(dir.join("missing")),
+            ..Default::default()
+        });
+        expect_that!(
+            result,
+            err(field!(Error.message, starts_with("failed to change")))
+        );
+    }
+
+    #[gtest]
+    fn times_out_slow_completion_functions() {
+        let dir = TempDir::new("bash-timeout");
+        std::fs::write(
+            dir.join("slow"),
+            "_slow() { sleep 0.5; touch done; }\ncomplete -F _slow slow\n",
+        )
+        .unwrap();
+        let mut session = BashSession::new(BashCompletionArgs {
+            dirs: vec![dir.to_path_buf()],
+            bash: "bash".into(),
+            timeout_ms: 100,
+        });
+
+        let result = session.complete(CompleteParams {
+            args: vec!["slow".into(), "".into()],
+            working_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        });
+        expect_that!(
+            result,
+            err(field!(Error.message, contains_substring("did not provide")))
+        );
+        // The completion function is killed too, not left running.
+        std::thread::sleep(Duration::from_millis(800));
+        expect_that!(dir.join("done").exists(), eq(false));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::system::{cache_home, now};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+/// The time (in seconds) between the prunings of the expired entries.
+const PRUNE_INTERVAL: u64 = 60 * 60;
+
+/// Values cached on disk (in `$XDG_CACHE_HOME/command-autocomplete/<name>`),
+/// shared between the processes. The cache is best effort, failing to read or
+/// write it is not an error. The expired and invalid entries are deleted when
+/// they are read, and (at most hourly) when another entry is written.
+#[derive(Clone)]
+pub struct DiskCache {
+    dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Entry<T> {
+    key: String,
+    /// The time (in seconds since the unix epoch) after which the entry is
+    /// deleted, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u64>,
+    value: T,
+}
+
+impl DiskCache {
+    pub fn new(name: &str) -> Self {
+        DiskCache {
+            dir: cache_home().map(|dir| dir.join("command-autocomplete").join(name)),
+        }
+    }
+
+    pub fn in_dir(dir: PathBuf) -> Self {
+        DiskCache { dir: Some(dir) }
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let path = self.path(key)?;
+        let data = std::fs::read(&path).ok()?;
+        let entry = match serde_json::from_slice::<Entry<serde_json::Value>>(&data) {
+            Ok(entry) if is_expired(&entry, now()) => {
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+            Ok(entry) => entry,
+            Err(err) => {
+                log::debug!("deleting invalid cache entry {}: {err}", path.display());
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+        };
+        // The files are named after the hash of the key, so the key is
+        // compared in case of collisions.
+        if entry.key != key {
+            return None;
+        }
+        serde_json::from_value(entry.value).ok()
+    }
+
+    pub fn put<T: Serialize>(&self, key: &str, value: &T) {
+        self.write(key, value, None);
+    }
+
+    /// Stores the value until the ttl (in seconds) elapses.
+    pub fn put_with_ttl<T: Serialize>(&self, key: &str, value: &T, ttl: u64) {
+        self.write(key, value, Some(now().saturating_add(ttl)));
+    }
+
+    pub fn remove(&self, key: &str) {
+        if let Some(path) = self.path(key) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn write<T: Serialize>(&self, key: &str, value: &T, expires: Option<u64>) {
+        let Some(path) = self.path(key) else {
+            return;
+        };
+        if self.prune_due() {
+            self.prune();
+        }
+        let entry = Entry {
+            key: key.to_string(),
+            expires,
+            value,
+        };
+        let result = serde_json::to_vec(&entry)
+            .map_err(std::io::Error::from)
+            .and_then(|data| write_atomic(&path, &data));
+        if let Err(err) = result {
+            log::warn!("failed to write cache entry {}: {err}", path.display());
+        }
+    }
+
+    /// Whether the entries were not pruned for [PRUNE_INTERVAL], marking them
+    /// as pruned now. The writes are frequent (e.g. on every completion in a
+    /// shell), and pruning reads all the entries.
+    fn prune_due(&self) -> bool {
+        let Some(marker) = self.dir.as_ref().map(|dir| dir.join(".pruned")) else {
+            return false;
+        };
+        let pruned = std::fs::metadata(&marker)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|time| time.elapsed().ok());
+        if pruned.is_some_and(|elapsed| elapsed.as_secs() < PRUNE_INTERVAL) {
+            return false;
+        }
+        let _ = std::fs::create_dir_all(marker.parent().unwrap_or(Path::new(".")))
+            .and_then(|_| std::fs::write(&marker, ""));
+        true
+    }
+
+    /// Deletes the expired and invalid entries.
+    fn prune(&self) {
+        let Some(Ok(files)) = self.dir.as_ref().map(std::fs::read_dir) else {
+            return;
+        };
+        let now = now();
+        for path in files.filter_map(|f| Some(f.ok()?.path())) {
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Ok(data) = std::fs::read(&path) else {
+                continue;
+            };
+            let valid = serde_json::from_slice::<Entry<serde::de::IgnoredAny>>(&data)
+                .is_ok_and(|entry| !is_expired(&entry, now));
+            if !valid {
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!("{:016x}.json", fnv1a(key.as_bytes()))))
+    }
+}
+
+/// Writes the file through a temporary file, so that the readers never see it
+/// partially written. The temporary file is unique to the write, so that the
+/// concurrent writes (of other processes or threads) don't mix.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let tmp = path.with_extension(format!(
+        "tmp{}-{}",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = std::fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))
+        .and_then(|_| std::fs::write(&tmp, data))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// A key identifying the version of the file (by its path and modification
+/// time), so that the values cached for it are not used after it changes.
+pub fn file_key(path: &Path) -> Option<String> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let nanos = modified.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    Some(format!("{}@{nanos}", path.display()))
+}
+
+fn is_expired<T>(entry: &Entry<T>, now: u64) -> bool {
+    entry.expires.is_some_and(|expires| expires <= now)
+}
+
+/// The FNV-1a hash, which (unlike the std hasher) is stable across the
+/// versions, so it can be used in the file names.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn stores_values_by_key() {
+        let dir = TempDir::new("cache");
+        let cache = DiskCache::in_dir(dir.to_path_buf());
+
+        expect_that!(cache.get::<Vec<String>>("a"), none());
+        cache.put("a", &vec!["x".to_string()]);
+        cache.put("b", &vec!["y".to_string()]);
+        expect_that!(cache.get::<Vec<String>>("a"), some(elements_are![eq("x")]));
+        expect_that!(cache.get::<Vec<String>>("b"), some(elements_are![eq("y")]));
+        // A value of another type is a cache miss.
+        expect_that!(cache.get::<u32>("a"), none());
+    }
+
+    #[gtest]
+    fn deletes_expired_and_invalid_entries() {
+        let dir = TempDir::new("cache-expired");
+        let cache = DiskCache::in_dir(dir.to_path_buf());
+        let files = || {
+            std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|f| f.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|e| e == "json"))
+                .count()
+        };
+
+        cache.put_with_ttl("a", &1, 0);
+        expect_that!(files(), eq(1));
+        expect_that!(cache.get::<u32>("a"), none());
+        expect_that!(files(), eq(0));
+
+        cache.put_with_ttl("b", &2, 60);
+        std::fs::write(cache.path("c").unwrap(), "invalid").unwrap();
+        cache.put_with_ttl("d", &4, 0);
+        cache.put("e", &5);
+        expect_that!(files(), eq(4));
+        // The invalid and the expired entries are pruned on write, once the
+        // prune interval elapses.
+        std::fs::remove_file(dir.join(".pruned")).unwrap();
+        cache.put("f", &6);
+        expect_that!(files(), eq(3));
+        expect_that!(cache.get::<u32>("b"), some(eq(2)));
+        expect_that!(cache.get::<u32>("e"), some(eq(5)));
+    }
+}
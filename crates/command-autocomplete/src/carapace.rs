@@ -1,5 +1,4 @@
-use crate::connection::{ConnRequest, SendError, Transport};
-use crate::types::{CompleteParams, CompleteResult, CompletionValue, Error, ShutdownResult};
+use crate::types::{CompleteParams, CompleteResult, CompletionValue, Diagnostic, Error, Severity};
 use clap::Args;
 use serde::{Deserialize, Serialize};
 use std::process::Command;
@@ -8,53 +7,7 @@ use std::process::Command;
 pub struct CarapaceArgs {}
 
 pub fn run_carapace(_args: CarapaceArgs) -> anyhow::Result<()> {
-    let (transport, join_handle) = Transport::stdio();
-    {
-        let (_, receiver) = crate::connection::new_connection(transport);
-        while let Some(req) = receiver.next_request() {
-            match handle_request(req) {
-                Ok(LoopAction::Continue) => continue,
-                Ok(LoopAction::Stop) => break,
-                Err(_) => {
-                    log::warn!("the connection closed unexpectedly, stopping the receving loop");
-                    break;
-                }
-            }
-        }
-    }
-    join_handle.join()?;
-    Ok(())
-}
-enum LoopAction {
-    Continue,
-    Stop,
-}
-
-fn handle_request(req: ConnRequest) -> Result<LoopAction, SendError> {
-    match req.inner().method.as_str() {
-        "complete" => match serde_json::from_value(req.inner().params.clone()) {
-            Ok(params) => {
-                req.reply(handle_complete_request(params))?;
-            }
-            Err(err) => {
-                req.reply_err(Error::invalid_request(format!(
-                    "invalid params for complete request: {err}"
-                )))?;
-            }
-        },
-        "shutdown" => {
-            req.reply_ok(ShutdownResult {})?;
-            return Ok(LoopAction::Stop);
-        }
-        _ => {
-            let method = req.inner().method.clone();
-            req.reply_err(Error {
-                code: "UNKNOWN_REQUEST".to_string(),
-                message: format!("method {} is not recognized", method),
-            })?;
-        }
-    }
-    Ok(LoopAction::Continue)
+    crate::server::serve(handle_complete_request)
 }
 
 fn handle_complete_request(params: CompleteParams) -> Result<CompleteResult, Error> {
@@ -68,31 +21,82 @@ fn handle_complete_request(params: CompleteParams) -> Result<CompleteResult, Err
     args.push(params.args[0].clone());
     args.push("export".into());
     args.extend_from_slice(&params.args);
-    let output = Command::new("carapace")
-        .args(args)
+    let mut command = Command::new("carapace");
+    command.args(args);
+    params.apply_to(&mut command);
+    let output = command
         .output()
         .map_err(|e| Error::internal(format!("failed to run carapace command: {e}")))?;
     if !output.status.success() {
-        return Err(Error::internal("carapace command failed"));
+        return Err(Error::internal(format!(
+            "carapace command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
     }
 
     let carapace_export: CarapaceExport = serde_json::from_slice(&output.stdout)
         .map_err(|e| Error::internal(format!("output from carapace can't be parsed: {e}")))?;
 
-    Ok(CompleteResult {
-        values: carapace_export
+    Ok(convert_export(carapace_export))
+}
+
+fn convert_export(export: CarapaceExport) -> CompleteResult {
+    let nospace = export.nospace;
+    CompleteResult {
+        values: export
             .values
             .into_iter()
             .map(|x| CompletionValue {
+                no_space: matches_suffix(&nospace, &x.value),
+                // Carapace always sets the display, so we skip it when it
+                // does not differ from the value.
+                display: x.display.filter(|display| *display != x.value),
                 value: x.value,
-                description: x.description,
+                description: x.description.filter(|d| !d.is_empty()),
+                tag: x.tag.filter(|t| !t.is_empty()),
+                style: x.style.filter(|s| !s.is_empty()),
+                ..Default::default()
+            })
+            .collect(),
+        usage: Some(export.usage).filter(|u| !u.is_empty()),
+        diagnostics: export
+            .messages
+            .into_iter()
+            .map(|message| Diagnostic {
+                severity: Severity::Error,
+                message,
             })
             .collect(),
-    })
+        ..Default::default()
+    }
+}
+
+/// Checks if the value ends with one of the characters of carapace suffix
+/// matcher (where `*` matches everything).
+fn matches_suffix(matcher: &str, value: &str) -> bool {
+    if matcher.contains('*') {
+        return true;
+    }
+    value
+        .chars()
+        .last()
+        .is_some_and(|last| matcher.contains(last))
 }
 
+/// The output of `carapace <command> export`.
 #[derive(Debug, Deserialize, Serialize)]
 struct CarapaceExport {
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub messages: Vec<String>,
+    /// The suffixes (characters) after which no space should be inserted.
+    #[serde(default)]
+    pub nospace: String,
+    #[serde(default)]
+    pub usage: String,
+    #[serde(default)]
     pub values: Vec<CarapaceValue>,
 }
 
@@ -101,5 +105,68 @@ struct CarapaceValue {
     pub value: String,
     pub display: Option<String>,
     pub description: Option<String>,
+    pub style: Option<String>,
     pub tag: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn converts_export() {
+        let export: CarapaceExport = serde_json::from_str(
+            r#"{
+              "version": "v1.0.0",
+              "messages": ["unknown shorthand flag: 'x' in -x"],
+              "nospace": "/",
+              "usage": "list directory contents",
+              "values": [
+                {"value": "--all", "display": "--all", "description": "do not ignore entries starting with .", "style": "blue", "tag": "flags"},
+                {"value": "src/", "display": "src/", "style": "bold blue", "tag": "directories"},
+                {"value": "-l", "display": "-l (long)", "description": "", "style": "", "tag": ""}
+              ]
+            }"#,
+        )
+        .unwrap();
+        let result = convert_export(export);
+        expect_that!(result.usage, some(eq("list directory contents")));
+        expect_that!(
+            result.diagnostics,
+            elements_are![matches_pattern!(Diagnostic {
+                severity: eq(&Severity::Error),
+                message: eq("unknown shorthand flag: 'x' in -x"),
+            })]
+        );
+        expect_that!(
+            result.values,
+            elements_are![
+                matches_pattern!(CompletionValue {
+                    value: eq("--all"),
+                    description: some(eq("do not ignore entries starting with .")),
+                    display: none(),
+                    tag: some(eq("flags")),
+                    style: some(eq("blue")),
+                    no_space: eq(&false),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("src/"),
+                    description: none(),
+                    display: none(),
+                    tag: some(eq("directories")),
+                    style: some(eq("bold blue")),
+                    no_space: eq(&true),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("-l"),
+                    description: none(),
+                    display: some(eq("-l (long)")),
+                    tag: none(),
+                    style: none(),
+                    no_space: eq(&false),
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Importing the [carapace-spec](https://carapace-sh.github.io/carapace-spec/)
+//! files (YAML) into the [CommandSpec].
+//!
+//! The flags (with the `=` modifier for the ones taking a value), the
+//! positional completions and the `$files`, `$directories` and `$(command)`
+//! macros are supported, the other macros are ignored. Note that the shell
+//! commands get the args as `$0`, `$1`, ... instead of carapace's `$C_ARG0`.
+
+use crate::spec::{ArgSpec, CommandSpec, FlagSpec, ValueItem, ValueSpec};
+use serde::Deserialize;
+use serde_yaml::Mapping;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Command {
+    name: String,
+    aliases: Vec<String>,
+    description: Option<String>,
+    hidden: bool,
+    /// The flags, as `-o, --output=: description`.
+    flags: Mapping,
+    persistentflags: Mapping,
+    completion: Completion,
+    commands: Vec<Command>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Completion {
+    /// The values of the flags, by their long (or short) name without dashes.
+    flag: HashMap<String, Vec<String>>,
+    positional: Vec<Vec<String>>,
+    positionalany: Vec<String>,
+}
+
+pub fn parse(content: &str) -> anyhow::Result<CommandSpec> {
+    let command: Command = serde_yaml::from_str(content)?;
+    Ok(convert(command))
+}
+
+fn convert(command: Command) -> CommandSpec {
+    let flags = command
+        .flags
+        .iter()
+        .map(|flag| (flag, false))
+        .chain(command.persistentflags.iter().map(|flag| (flag, true)));
+    let mut args: Vec<ArgSpec> = command
+        .completion
+        .positional
+        .iter()
+        .enumerate()
+        .map(|(i, values)| ArgSpec {
+            name: format!("arg{}", i + 1),
+            value: value_spec(values),
+            ..Default::default()
+        })
+        .collect();
+    if !command.completion.positionalany.is_empty() {
+        args.push(ArgSpec {
+            name: "args".to_string(),
+            variadic: true,
+            value: value_spec(&command.completion.positionalany),
+            ..Default::default()
+        });
+    }
+    CommandSpec {
+        flags: flags
+            .filter_map(|((key, description), persistent)| {
+                flag(
+                    key.as_str()?,
+                    description.as_str(),
+                    persistent,
+                    &command.completion,
+                )
+            })
+            .collect(),
+        args,
+        commands: command
+            .commands
+            .into_iter()
+            .filter(|c| !c.hidden)
+            .map(convert)
+            .collect(),
+        name: command.name,
+        aliases: command.aliases,
+        description: command.description.filter(|d| !d.is_empty()),
+    }
+}
+
+/// Converts the flag, e.g. `-o, --output=`, with the modifiers at the end
+/// (`=` takes a value, `?` takes an optional value, `*` is repeatable, `!` is
+/// required, `&` is hidden).
+fn flag(
+    key: &str,
+    description: Option<&str>,
+    persistent: bool,
+    completion: &Completion,
+) -> Option<FlagSpec> {
+    let names = key.trim_end_matches(['=', '?', '*', '!', '&']);
+    let modifiers = &key[names.len()..];
+    if modifiers.contains('&') {
+        return None;
+    }
+    let names: Vec<String> = names
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    // The optional values can be only given as `--flag=value`, so the flag
+    // doesn't take the next arg.
+    let optional_value = modifiers.contains('?');
+    let value = (modifiers.contains('=') || optional_value).then(|| {
+        names
+            .iter()
+            .rev()
+            .find_map(|name| completion.flag.get(name.trim_start_matches('-')))
+            .map(|values| value_spec(values))
+            .unwrap_or_default()
+    });
+    Some(FlagSpec {
+        names,
+        description: description.filter(|d| !d.is_empty()).map(String::from),
+        value,
+        optional_value,
+        persistent,
+    })
+}
+
+/// Converts the values, e.g. `["a", "b\tdescription", "$files([.go])"]`.
+fn value_spec(values: &[String]) -> ValueSpec {
+    let mut spec = ValueSpec::default();
+    let mut commands = vec![];
+    for value in values {
+        if let Some(command) = value.strip_prefix("$(").and_then(|c| c.strip_suffix(')')) {
+            commands.push(command.to_string());
+        } else if value == "$files" {
+            spec.files = true;
+        } else if let Some(extensions) = value
+            .strip_prefix("$files(")
+            .and_then(|e| e.strip_suffix(')'))
+        {
+            spec.extensions.extend(
+                extensions
+                    .trim_matches(['[', ']'])
+                    .split(',')
+                    .map(|e| e.trim().trim_start_matches('.').to_string())
+                    .filter(|e| !e.is_empty()),
+            );
+        } else if value == "$directories" {
+            spec.dirs = true;
+        } else if value.starts_with('$') {
+            log::debug!("ignoring unsupported carapace macro {value}");
+        } else {
+            spec.values.push(match value.split_once('\t') {
+                Some((value, description)) => ValueItem::Described {
+                    value: value.to_string(),
+                    // The description can be followed by the style.
+                    description: description.split('\t').next().map(String::from),
+                },
+                None => ValueItem::Value(value.clone()),
+            });
+        }
+    }
+    if !commands.is_empty() {
+        spec.command = Some(commands.join("\n"));
+    }
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{complete_spec, values};
+    use crate::types::FilesHint;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn imports_carapace_spec() {
+        let content = std::fs::read_to_string("testdata/spec/mytool.carapace.yaml").unwrap();
+        let spec = parse(&content).unwrap();
+
+        expect_that!(spec.name, eq("mytool"));
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", ""]).values),
+            elements_are![eq("build"), eq("deploy")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "-"]).values),
+            elements_are![eq("--config"), eq("--color"), eq("-v"), eq("--verbose")]
+        );
+        // The optional value is completed only in the same arg.
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "--color=n"]).values),
+            elements_are![eq("--color=never")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "--color", "b"]).values),
+            elements_are![eq("build")]
+        );
+        expect_that!(
+            complete_spec(&spec, &["mytool", "--config", ""]).files,
+            some(eq(&FilesHint {
+                extensions: vec!["toml".into()],
+                ..Default::default()
+            }))
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "-v", "b", "--"]).values),
+            elements_are![eq("--verbose"), eq("--release"), eq("--target")]
+        );
+        let result = complete_spec(&spec, &["mytool", "b", "--target", ""]);
+        expect_that!(
+            values(&result.values),
+            elements_are![eq("linux"), eq("windows")]
+        );
+        expect_that!(
+            result.values[1].description.as_deref(),
+            some(eq("Windows (experimental)"))
+        );
+        let result = complete_spec(&spec, &["mytool", "deploy", ""]);
+        expect_that!(
+            values(&result.values),
+            elements_are![eq("prod"), eq("staging")]
+        );
+        expect_that!(
+            result.values[0].description.as_deref(),
+            some(eq("deploy prod from mytool"))
+        );
+        expect_that!(
+            complete_spec(&spec, &["mytool", "deploy", "prod", "x", ""]).files,
+            some(eq(&FilesHint {
+                directories_only: true,
+                ..Default::default()
+            }))
+        );
+    }
+}
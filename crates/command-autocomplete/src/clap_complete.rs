@@ -0,0 +1,93 @@
+use crate::types::{CompleteParams, CompleteResult, Error};
+use clap::Args;
+use std::process::Command;
+
+#[derive(Debug, Args)]
+pub struct ClapArgs {
+    /// The environment variable that enables the completions in the CLI.
+    #[arg(long, default_value = "COMPLETE")]
+    var: String,
+}
+
+pub fn run_clap(args: ClapArgs) -> anyhow::Result<()> {
+    crate::server::serve(|params| handle_complete_request(&args, params))
+}
+
+/// Runs `COMPLETE=fish <cli> -- <args>`, the dynamic completions of
+/// `clap_complete` for fish (which has the simplest output format).
+fn handle_complete_request(
+    args: &ClapArgs,
+    params: CompleteParams,
+) -> Result<CompleteResult, Error> {
+    if params.args.is_empty() {
+        return Err(Error::invalid_request(
+            "params.args is empty, required at least one element",
+        ));
+    }
+
+    let mut command = Command::new(&params.args[0]);
+    command.arg("--").args(&params.args);
+    params.apply_to(&mut command);
+    command.env(&args.var, "fish");
+    let output = command
+        .output()
+        .map_err(|e| Error::internal(format!("failed to run {}: {e}", params.args[0])))?;
+    if !output.status.success() {
+        return Err(Error::internal(format!(
+            "{} completion failed ({}): {}",
+            params.args[0],
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(crate::fish::parse_output(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::types::CompletionValue;
+    use googletest::prelude::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[gtest]
+    fn completes_with_env_var_protocol() {
+        let dir = TempDir::new("clap");
+        let cli = dir.join("mytool");
+        std::fs::write(
+            &cli,
+            "#!/bin/sh\n[ \"$MY_COMPLETE\" = fish ] || exit 1\nprintf '%s\\tArg\\n' \"$@\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&cli, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let args = ClapArgs {
+            var: "MY_COMPLETE".into(),
+        };
+        let result = handle_complete_request(
+            &args,
+            CompleteParams {
+                args: vec![cli.to_str().unwrap().into(), "--col".into()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        expect_that!(
+            result.values,
+            elements_are![
+                matches_pattern!(CompletionValue { value: eq("--") }),
+                matches_pattern!(CompletionValue {
+                    value: eq(cli.to_str().unwrap())
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("--col"),
+                    description: some(eq("Arg")),
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use crate::cache::DiskCache;
+use crate::connection::{ConnectionSender, JoinHandle, Transport};
+use crate::shell_words::{self, Dialect, ParsedLine};
+use crate::types::{
+    AcceptedParams, CompleteParams, CompleteResult, CompletionValue, EnvironmentVariable, Error,
+};
+use anyhow::Context;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+// Describes how the shell bridges reach the router.
+#[derive(Debug, Args)]
+pub struct ServerArgs {
+    /// The binary used to start the router (as `<ROUTER_BIN> router [CONFIG]`).
+    #[arg(
+        long,
+        env = "COMMAND_AUTOCOMPLETE_ROUTER_BIN",
+        default_value = "command-autocomplete"
+    )]
+    router_bin: String,
+    /// The configuration path passed to the started router.
+    #[arg(long, env = "COMMAND_AUTOCOMPLETE_ROUTER_CONFIG")]
+    router_config: Option<PathBuf>,
+    /// The socket of an already running router (see `router --socket`).
+    ///
+    /// When set, the router is not started and the other router options are
+    /// ignored.
+    #[arg(long, env = "COMMAND_AUTOCOMPLETE_SOCKET")]
+    socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct AcceptedArgs {
+    #[command(flatten)]
+    server: ServerArgs,
+    /// The inserted value.
+    #[arg(long, required_unless_present = "line", conflicts_with = "line")]
+    value: Option<String>,
+    /// The executed command line, checked for a value of the last completion
+    /// of the shell (made by the shell bridges).
+    #[arg(long)]
+    line: Option<String>,
+    /// The shell whose syntax is used for splitting the line.
+    #[arg(long, value_enum, default_value = "bash")]
+    dialect: Dialect,
+    /// args of the command that were completed
+    #[arg(last = true)]
+    command: Vec<String>,
+}
+
+/// Notifies the router that the user inserted the value, for the shells that
+/// can run a command after the insertion, or before executing the line.
+pub fn run_accepted(args: AcceptedArgs) -> anyhow::Result<()> {
+    let params = match (args.value, args.line) {
+        (Some(value), _) => AcceptedParams {
+            args: args.command,
+            value,
+            working_dir: std::env::current_dir().ok(),
+        },
+        (None, Some(line)) => {
+            let Some(params) = LastCompletion::take()
+                .and_then(|last| last.accepted(&shell_words::split(&line, args.dialect)))
+            else {
+                return Ok(());
+            };
+            params
+        }
+        (None, None) => unreachable!("required by clap"),
+    };
+    accepted(&args.server, params)
+}
+
+/// The last completion of the shell (the parent process of the bridges),
+/// saved so that the value inserted from it can be found in the executed
+/// line.
+#[derive(Deserialize, Serialize)]
+struct LastCompletion {
+    args: Vec<String>,
+    working_dir: Option<PathBuf>,
+    values: Vec<String>,
+}
+
+impl LastCompletion {
+    /// The time (in seconds) for which the completion is kept.
+    const TTL: u64 = 600;
+
+    fn save(params: &CompleteParams, values: &[CompletionValue]) {
+        let Some(key) = shell_key() else {
+            return;
+        };
+        let last = LastCompletion {
+            args: params.args.clone(),
+            working_dir: params.working_dir.clone(),
+            values: values.iter().map(|v| v.value.clone()).collect(),
+        };
+        DiskCache::new("completions").put_with_ttl(&key, &last, Self::TTL);
+    }
+
+    fn take() -> Option<LastCompletion> {
+        let key = shell_key()?;
+        let cache = DiskCache::new("completions");
+        let last = cache.get(&key);
+        cache.remove(&key);
+        last
+    }
+
+    /// Returns the accepted value, if the executed args contain the completed
+    /// args followed by one of the values.
+    fn accepted(self, executed: &[String]) -> Option<AcceptedParams> {
+        let (_, completed) = self.args.split_last()?;
+        let value = executed.windows(completed.len() + 1).find_map(|window| {
+            let (arg, prefix) = window.split_last()?;
+            if prefix != completed {
+                return None;
+            }
+            self.values.iter().find(|v| v.trim_end() == arg)
+        })?;
+        Some(AcceptedParams {
+            value: value.clone(),
+            args: self.args,
+            working_dir: self.working_dir,
+        })
+    }
+}
+
+/// Identifies the shell by the parent process of the bridge.
+#[cfg(unix)]
+fn shell_key() -> Option<String> {
+    Some(std::os::unix::process::parent_id().to_string())
+}
+
+#[cfg(not(unix))]
+fn shell_key() -> Option<String> {
+    None
+}
+
+// The command being completed, given either as args or as a command line.
+#[derive(Debug, Args)]
+pub struct CommandArgs {
+    /// The command line to complete, split into args by the bridge.
+    #[arg(long, conflicts_with = "command")]
+    line: Option<String>,
+    /// The cursor position in the line (in bytes), defaults to the end of the
+    /// line.
+    #[arg(long, requires = "line")]
+    cursor: Option<usize>,
+    /// Don't use the cached results of the completers.
+    #[arg(long)]
+    refresh: bool,
+    /// The maximal number of the returned values (no limit by default). The
+    /// shells don't request the completion again when the word changes, so
+    /// the trimmed values are listed only after typing more characters and
+    /// completing again.
+    #[arg(long, env = "COMMAND_AUTOCOMPLETE_MAX_RESULTS")]
+    max_results: Option<usize>,
+    /// args of the command that is being completed
+    #[arg(last = true)]
+    command: Vec<String>,
+}
+
+impl CommandArgs {
+    /// Returns the parsed line and the params for completing it in the
+    /// environment of the current process.
+    pub fn into_params(self, dialect: Dialect) -> (ParsedLine, CompleteParams) {
+        let refresh = self.refresh;
+        let max_results = self.max_results;
+        let line = self.parse(dialect);
+        let mut params = complete_params(line.args.clone());
+        params.refresh = refresh;
+        params.max_results = max_results;
+        (line, params)
+    }
+
+    /// Returns the args to complete, splitting the line if needed.
+    fn parse(self, dialect: Dialect) -> ParsedLine {
+        match self.line {
+            Some(line) => {
+                let cursor = self.cursor.unwrap_or(line.len());
+                shell_words::parse(&line, cursor, dialect)
+            }
+            None => ParsedLine {
+                args: self.command,
+                current: 0..0,
+                quote: None,
+            },
+        }
+    }
+}
+
+/// A connection to the router (or any other Command Autocomplete Server).
+pub struct Client {
+    sender: ConnectionSender,
+    child: Option<Child>,
+    recv_join_handle: std::thread::JoinHandle<()>,
+    join_handle: JoinHandle,
+}
+
+impl Client {
+    /// Connects to the server described by the args, starting it if needed.
+    pub fn connect(args: &ServerArgs) -> anyhow::Result<Client> {
+        if let Some(socket) = &args.socket {
+            return Self::connect_socket(socket);
+        }
+        let mut command = Command::new(&args.router_bin);
+        command.arg("router");
+        if let Some(config) = &args.router_config {
+            command.arg(config);
+        }
+        Self::spawn(command)
+    }
+
+    /// Starts the server as a subprocess and connects to its stdio.
+    pub fn spawn(mut command: Command) -> anyhow::Result<Client> {
+        log::debug!("starting the server: {:?}", command);
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to start the server {:?}", command))?;
+
+        let stdin = child.stdin.take().context("missing stdin")?;
+        let stdout = child.stdout.take().context("missing stdout")?;
+
+        let (transport, join_handle) = Transport::raw(stdout, stdin);
+        Ok(Self::new(transport, join_handle, Some(child)))
+    }
+
+    #[cfg(unix)]
+    fn connect_socket(socket: &std::path::Path) -> anyhow::Result<Client> {
+        let stream = std::os::unix::net::UnixStream::connect(socket)
+            .with_context(|| format!("failed to connect to {}", socket.display()))?;
+        let (transport, join_handle) = Transport::unix(stream)?;
+        Ok(Self::new(transport, join_handle, None))
+    }
+
+    #[cfg(not(unix))]
+    fn connect_socket(_socket: &std::path::Path) -> anyhow::Result<Client> {
+        anyhow::bail!("connecting to the router socket is only supported on unix")
+    }
+
+    fn new(transport: Transport, join_handle: JoinHandle, child: Option<Child>) -> Client {
+        let (sender, receiver) = crate::connection::new_connection(transport);
+        let recv_join_handle = std::thread::spawn(move || {
+            // This is required to read the incoming responses.
+            while let Some(req) = receiver.next_request() {
+                let r = req.reply_err(Error::invalid_request("no requests expected"));
+                if r.is_err() {
+                    log::warn!("The connection closed unexpectedly, stopping the receving loop");
+                    break;
+                }
+            }
+        });
+        Client {
+            sender,
+            child,
+            recv_join_handle,
+            join_handle,
+        }
+    }
+
+    pub fn complete(&self, params: CompleteParams) -> anyhow::Result<CompleteResult> {
+        let res_handle = self
+            .sender
+            .send("complete", params)
+            .context("complete command failed")?;
+        res_handle.wait().context("complete command failed")
+    }
+
+    /// Notifies the server that the user inserted the value, out of the
+    /// values completed for the args.
+    pub fn accepted(&self, params: AcceptedParams) -> anyhow::Result<()> {
+        self.sender
+            .notify("accepted", params)
+            .context("accepted notification failed")
+    }
+
+    /// Shuts down the connection and waits for the server to finish.
+    ///
+    /// If the server does not respond to the shutdown, it is killed.
+    pub fn shutdown(self) -> anyhow::Result<()> {
+        let Client {
+            sender,
+            mut child,
+            recv_join_handle,
+            join_handle,
+        } = self;
+        let res = sender
+            .shutdown()
+            .map_err(anyhow::Error::from)
+            .and_then(|handle| Ok(handle.wait()?))
+            .context("shutdown failed");
+        if let Some(child) = &mut child {
+            if res.is_err() {
+                log::warn!("Shutdown failed, will kill subprocess");
+                if let Err(err) = child.kill() {
+                    log::warn!("Failed to kill subprocess: {err}")
+                }
+            }
+            if let Err(err) = child.wait() {
+                log::warn!("Failed to wait for the subprocess: {err}");
+            }
+        }
+        if let Err(err) = recv_join_handle.join() {
+            log::warn!("receiving thread failed: {:?}", err);
+        }
+        if let Err(err) = join_handle.join() {
+            log::warn!("connection threads failed: {:?}", err);
+        }
+        res.map(|_| ())
+    }
+}
+
+/// Connects to the server, requests the completions and shuts the connection down.
+pub fn complete(args: &ServerArgs, params: CompleteParams) -> anyhow::Result<CompleteResult> {
+    let client = Client::connect(args)?;
+    let result = client.complete(params);
+    if let Err(err) = client.shutdown() {
+        log::warn!("{err:#}");
+    }
+    result
+}
+
+/// Requests the completions for a shell bridge, remembering them for
+/// `shell accepted --line`. The shells expect a list, so on failure the error
+/// is logged and no values are returned instead of failing the whole
+/// completion.
+pub fn complete_in_shell(args: &ServerArgs, params: CompleteParams) -> Vec<CompletionValue> {
+    let last_params = params.clone();
+    match complete(args, params) {
+        Ok(result) => {
+            if result.is_incomplete {
+                log::warn!(
+                    "Only {} values are listed, type more characters to list the rest",
+                    result.values.len()
+                );
+            }
+            LastCompletion::save(&last_params, &result.values);
+            result.values
+        }
+        Err(err) => {
+            log::error!("Completion failed: {err:#}");
+            vec![]
+        }
+    }
+}
+
+/// Connects to the server, notifies it about the accepted value and shuts the
+/// connection down.
+pub fn accepted(args: &ServerArgs, params: AcceptedParams) -> anyhow::Result<()> {
+    let client = Client::connect(args)?;
+    let result = client.accepted(params);
+    if let Err(err) = client.shutdown() {
+        log::warn!("{err:#}");
+    }
+    result
+}
+
+/// Creates the params for completing the args in the environment of the
+/// current process.
+pub fn complete_params(args: Vec<String>) -> CompleteParams {
+    CompleteParams {
+        args,
+        working_dir: std::env::current_dir().ok(),
+        envs: std::env::vars()
+            .map(|(name, value)| EnvironmentVariable { name, value })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn accepted(executed: &[&str]) -> Option<AcceptedParams> {
+        let last = LastCompletion {
+            args: vec!["git".into(), "checkout".into(), "ma".into()],
+            working_dir: Some("/src".into()),
+            values: vec!["main ".into(), "master ".into()],
+        };
+        let executed: Vec<String> = executed.iter().map(|a| a.to_string()).collect();
+        last.accepted(&executed)
+    }
+
+    #[gtest]
+    fn finds_accepted_value_in_executed_line() {
+        expect_that!(
+            accepted(&["git", "checkout", "main", "--", "file"]),
+            some(matches_pattern!(AcceptedParams {
+                args: elements_are![eq("git"), eq("checkout"), eq("ma")],
+                value: eq("main "),
+                working_dir: some(eq(std::path::Path::new("/src"))),
+            }))
+        );
+        expect_that!(
+            accepted(&["cd", "src", "git", "checkout", "master"]),
+            some(field!(AcceptedParams.value, eq("master ")))
+        );
+        expect_that!(accepted(&["git", "checkout", "mai"]), none());
+        expect_that!(accepted(&["git", "switch", "main"]), none());
+    }
+}
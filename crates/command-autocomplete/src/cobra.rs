@@ -0,0 +1,191 @@
+use crate::types::{
+    CompleteParams, CompleteResult, CompletionValue, Diagnostic, Error, FilesHint, Severity,
+};
+use clap::Args;
+use std::process::Command;
+
+#[derive(Debug, Args)]
+pub struct CobraArgs {}
+
+pub fn run_cobra(_args: CobraArgs) -> anyhow::Result<()> {
+    crate::server::serve(handle_complete_request)
+}
+
+fn handle_complete_request(params: CompleteParams) -> Result<CompleteResult, Error> {
+    if params.args.is_empty() {
+        return Err(Error::invalid_request(
+            "params.args is empty, required at least one element",
+        ));
+    }
+
+    let mut command = Command::new(&params.args[0]);
+    command.arg("__complete").args(&params.args[1..]);
+    params.apply_to(&mut command);
+    let output = command
+        .output()
+        .map_err(|e| Error::internal(format!("failed to run {}: {e}", params.args[0])))?;
+    if !output.status.success() {
+        return Err(Error::internal(format!(
+            "{} __complete failed ({}): {}",
+            params.args[0],
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    parse_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The bits of the directive printed by cobra after the values.
+mod directive {
+    pub const ERROR: u32 = 1;
+    pub const NO_SPACE: u32 = 2;
+    pub const NO_FILE_COMP: u32 = 4;
+    pub const FILTER_FILE_EXT: u32 = 8;
+    pub const FILTER_DIRS: u32 = 16;
+    pub const KEEP_ORDER: u32 = 32;
+}
+
+/// The prefix of the values that are help for the user, instead of
+/// completions.
+const ACTIVE_HELP_PREFIX: &str = "_activeHelp_ ";
+
+/// Parses the output of `__complete`, with a `value<TAB>description` per line
+/// and the `:<directive>` as the last line.
+fn parse_output(output: &str) -> Result<CompleteResult, Error> {
+    let mut lines: Vec<_> = output.lines().filter(|l| !l.is_empty()).collect();
+    let directive = match lines.pop().and_then(|l| l.strip_prefix(':')) {
+        Some(directive) => directive.trim().parse::<u32>().map_err(|e| {
+            Error::internal(format!("invalid directive {directive:?} from cobra: {e}"))
+        })?,
+        None => return Err(Error::internal("the directive is missing in cobra output")),
+    };
+
+    let mut result = CompleteResult {
+        keep_order: directive & directive::KEEP_ORDER != 0,
+        ..Default::default()
+    };
+    let mut values = vec![];
+    for line in lines {
+        if let Some(help) = line.strip_prefix(ACTIVE_HELP_PREFIX) {
+            result.diagnostics.push(Diagnostic {
+                severity: Severity::Info,
+                message: help.to_string(),
+            });
+            continue;
+        }
+        let (value, description) = match line.split_once('\t') {
+            Some((value, description)) => (value, Some(description)),
+            None => (line, None),
+        };
+        values.push(CompletionValue {
+            value: value.to_string(),
+            description: description
+                .map(|d| d.trim())
+                .filter(|d| !d.is_empty())
+                .map(|d| d.to_string()),
+            no_space: directive & directive::NO_SPACE != 0,
+            ..Default::default()
+        });
+    }
+
+    if directive & directive::ERROR != 0 {
+        result.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            message: "the completion failed".to_string(),
+        });
+    } else if directive & directive::FILTER_FILE_EXT != 0 {
+        // The values are the extensions of the files to complete.
+        result.files = Some(FilesHint {
+            extensions: values.into_iter().map(|v| v.value).collect(),
+            ..Default::default()
+        });
+    } else if directive & directive::FILTER_DIRS != 0 {
+        // The value, if any, is the directory in which to complete.
+        result.files = Some(FilesHint {
+            directories_only: true,
+            root: values.into_iter().next().map(|v| v.value.into()),
+            ..Default::default()
+        });
+    } else {
+        if values.is_empty() && directive & directive::NO_FILE_COMP == 0 {
+            result.files = Some(FilesHint::default());
+        }
+        result.values = values;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn parses_values_and_directive() {
+        let result =
+            parse_output("pods\tList pods\n_activeHelp_ Choose a resource\nnodes\t\n:38\n")
+                .unwrap();
+        expect_that!(
+            result.values,
+            elements_are![
+                matches_pattern!(CompletionValue {
+                    value: eq("pods"),
+                    description: some(eq("List pods")),
+                    no_space: eq(&true),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("nodes"),
+                    description: none(),
+                    no_space: eq(&true),
+                }),
+            ]
+        );
+        expect_that!(result.keep_order, eq(true));
+        expect_that!(result.files, none());
+        expect_that!(
+            result.diagnostics,
+            elements_are![matches_pattern!(Diagnostic {
+                severity: eq(&Severity::Info),
+                message: eq("Choose a resource"),
+            })]
+        );
+    }
+
+    #[gtest]
+    fn maps_file_directives() {
+        let result = parse_output(":0\n").unwrap();
+        expect_that!(result.files, some(eq(&FilesHint::default())));
+
+        let result = parse_output(":4\n").unwrap();
+        expect_that!(result.files, none());
+
+        let result = parse_output("yaml\njson\n:8\n").unwrap();
+        expect_that!(result.values, empty());
+        expect_that!(
+            result.files,
+            some(eq(&FilesHint {
+                extensions: vec!["yaml".into(), "json".into()],
+                ..Default::default()
+            }))
+        );
+
+        let result = parse_output("themes\n:16\n").unwrap();
+        expect_that!(
+            result.files,
+            some(eq(&FilesHint {
+                directories_only: true,
+                root: Some("themes".into()),
+                ..Default::default()
+            }))
+        );
+
+        let result = parse_output(":1\n").unwrap();
+        expect_that!(
+            result.diagnostics,
+            elements_are![matches_pattern!(Diagnostic {
+                severity: eq(&Severity::Error),
+            })]
+        );
+    }
+}
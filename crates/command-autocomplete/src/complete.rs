@@ -1,82 +1,437 @@
-use crate::{
-    connection::{ConnRequest, SendError, Transport},
-    types::{CompleteParams, CompleteResult, CompletionValue, Error, ShutdownResult},
-};
+//! Completing the args of a command described with clap (its subcommands,
+//! flags, possible values and value hints), which is how this binary completes
+//! itself.
 
-pub fn run_complete() -> anyhow::Result<()> {
-    let (transport, join_handle) = Transport::stdio();
+use crate::providers::Provider;
+use crate::types::{CompleteParams, CompleteResult, CompletionValue, Error, FilesHint};
+use clap::{Arg, ValueHint};
+
+/// Parses the args like [clap::Parser::parse], but when the CLI is run as
+/// `<cli> complete`, serves its completions instead (and exits), e.g.:
+///
+/// ```no_run
+/// #[derive(clap::Parser)]
+/// struct Cli {
+///     name: String,
+/// }
+///
+/// let cli: Cli = command_autocomplete::parse_or_complete();
+/// ```
+///
+/// The `complete` arg is checked before the parsing, so it takes precedence
+/// over the subcommands and positional args of the CLI.
+pub fn parse_or_complete<T: clap::Parser>() -> T {
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|arg| arg == "complete")
     {
-        let (_, receiver) = crate::connection::new_connection(transport);
-        while let Some(req) = receiver.next_request() {
-            match handle_request(req) {
-                Ok(LoopAction::Continue) => continue,
-                Ok(LoopAction::Stop) => break,
-                Err(_) => {
-                    log::warn!("the connection closed unexpectedly, stopping the receving loop");
-                    break;
-                }
+        let code = match run_complete(T::command()) {
+            Ok(()) => 0,
+            Err(err) => {
+                eprintln!("Error: {err:#}");
+                1
             }
-        }
+        };
+        std::process::exit(code);
     }
-    join_handle.join()?;
-    Ok(())
+    T::parse()
 }
 
-enum LoopAction {
-    Continue,
-    Stop,
+/// The hidden `complete` subcommand, for the CLIs built with the clap builder,
+/// which should call [run_complete] when it is matched:
+///
+/// ```no_run
+/// let command = clap::Command::new("cli")
+///     .subcommand(clap::Command::new("build"))
+///     .subcommand(command_autocomplete::complete_subcommand());
+/// match command.clone().get_matches().subcommand() {
+///     Some(("complete", _)) => command_autocomplete::run_complete(command).unwrap(),
+///     _ => {}
+/// }
+/// ```
+pub fn complete_subcommand() -> clap::Command {
+    clap::Command::new("complete")
+        .about("Serves the completions (Command Autocomplete Protocol)")
+        .hide(true)
 }
 
-fn handle_request(req: ConnRequest) -> Result<LoopAction, SendError> {
-    match req.inner().method.as_str() {
-        "complete" => match serde_json::from_value(req.inner().params.clone()) {
-            Ok(params) => {
-                req.reply(handle_complete_request(params))?;
-            }
-            Err(err) => {
-                req.reply_err(Error::invalid_request(format!(
-                    "invalid params for complete request: {err}"
-                )))?;
-            }
+/// Serves the completions of the command.
+pub fn run_complete(mut command: clap::Command) -> anyhow::Result<()> {
+    // Building adds the help and version flags, and propagates the global
+    // args to the subcommands.
+    command.build();
+    crate::server::serve(|params| {
+        let mut result = complete_command(&command, params.clone())?;
+        crate::path::expand_files_hint(&mut result, &params);
+        Ok(result)
+    })
+}
+
+/// Completes the args (starting with the program name) of the command, which
+/// should be built with [clap::Command::build].
+pub fn complete_command(
+    command: &clap::Command,
+    params: CompleteParams,
+) -> Result<CompleteResult, Error> {
+    let Some((current, args)) = params.args.split_last() else {
+        return Err(Error::invalid_request(
+            "params.args is empty, required at least one element",
+        ));
+    };
+    if args.is_empty() {
+        return Ok(CompleteResult::default());
+    }
+
+    let (command, position) = locate_arg(
+        &params.args,
+        command,
+        |command, arg| {
+            find_flag(command, arg)
+                .filter(|(_, inline)| !inline)
+                .map(|(f, _)| f)
         },
-        "shutdown" => {
-            req.reply_ok(ShutdownResult {})?;
-            return Ok(LoopAction::Stop);
+        |command, arg| command.find_subcommand(arg),
+    );
+    match position {
+        ArgPosition::FlagValue(flag) => Ok(complete_value(flag, current, &params)),
+        ArgPosition::InlineFlagValue { name, prefix } => {
+            let Some((flag, _)) = find_flag(command, name) else {
+                return Ok(CompleteResult::default());
+            };
+            let result = complete_value(flag, prefix, &params);
+            Ok(complete_inline_value(name, prefix, result, &params))
         }
-        _ => {
-            let method = req.inner().method.clone();
-            req.reply_err(Error {
-                code: "UNKNOWN_REQUEST".to_string(),
-                message: format!("method {} is not recognized", method),
-            })?;
+        ArgPosition::Flag => Ok(CompleteResult {
+            values: flags(command)
+                .filter(|v| v.value.starts_with(current.as_str()))
+                .collect(),
+            ..Default::default()
+        }),
+        ArgPosition::Positional { index, subcommands } => {
+            let mut result = match positional_arg(command, index) {
+                Some(arg) => complete_value(arg, current, &params),
+                None => CompleteResult::default(),
+            };
+            if subcommands {
+                let subcommands = command
+                    .get_subcommands()
+                    .filter(|c| !c.is_hide_set() && c.get_name().starts_with(current.as_str()))
+                    .map(|c| CompletionValue {
+                        value: c.get_name().to_string(),
+                        description: c.get_about().map(|about| about.to_string()),
+                        ..Default::default()
+                    });
+                result.values.splice(0..0, subcommands);
+            }
+            Ok(result)
         }
     }
-    Ok(LoopAction::Continue)
 }
 
-fn handle_complete_request(params: CompleteParams) -> Result<CompleteResult, Error> {
-    let mut completions = vec![];
-    if params.args.len() == 2 {
-        completions = vec![
-            CompletionValue {
-                value: "shell ".into(),
-                description: None,
-            },
-            CompletionValue {
-                value: "router ".into(),
-                description: None,
-            },
-            CompletionValue {
-                value: "bridge ".into(),
-                description: None,
+/// What the completed (last) arg is, found by [locate_arg].
+pub enum ArgPosition<'a, F> {
+    /// The value of the flag in the previous arg.
+    FlagValue(F),
+    /// The value of the flag given in the same arg, e.g. `--flag=<prefix>`.
+    InlineFlagValue { name: &'a str, prefix: &'a str },
+    /// A flag.
+    Flag,
+    /// The positional arg at the index (among the args of the command), or a
+    /// subcommand.
+    Positional { index: usize, subcommands: bool },
+}
+
+/// Walks the args (starting with the program name) before the completed one,
+/// entering the subcommands, and returns the command and the position of the
+/// completed arg in it.
+///
+/// `flag_with_value` returns the flag given by the arg (e.g. `--output`, `-o`
+/// or `-vo`) when its value is the next arg, and `subcommand` the subcommand
+/// named by the arg.
+pub fn locate_arg<'a, C, F>(
+    args: &'a [String],
+    mut command: C,
+    flag_with_value: impl Fn(&C, &str) -> Option<F>,
+    subcommand: impl Fn(&C, &str) -> Option<C>,
+) -> (C, ArgPosition<'a, F>) {
+    let Some((current, args)) = args.split_last() else {
+        return (
+            command,
+            ArgPosition::Positional {
+                index: 0,
+                subcommands: false,
             },
-            CompletionValue {
-                value: "complete ".into(),
-                description: None,
+        );
+    };
+    let mut positional = 0;
+    let mut flag_value: Option<F> = None;
+    let mut only_positional = false;
+    for arg in args.iter().skip(1) {
+        if flag_value.take().is_some() {
+            continue;
+        }
+        if !only_positional && arg == "--" {
+            only_positional = true;
+        } else if !only_positional && arg.starts_with('-') && arg.len() > 1 {
+            flag_value = flag_with_value(&command, arg);
+        } else if let Some(sub) =
+            subcommand(&command, arg).filter(|_| positional == 0 && !only_positional)
+        {
+            command = sub;
+        } else {
+            positional += 1;
+        }
+    }
+
+    let position = match flag_value {
+        Some(flag) => ArgPosition::FlagValue(flag),
+        None if !only_positional && current.starts_with('-') => match current.split_once('=') {
+            Some((name, prefix)) => ArgPosition::InlineFlagValue { name, prefix },
+            None => ArgPosition::Flag,
+        },
+        None => ArgPosition::Positional {
+            index: positional,
+            subcommands: positional == 0 && !only_positional,
+        },
+    };
+    (command, position)
+}
+
+/// Completes the value of the flag given in the same arg (`--flag=<prefix>`),
+/// prefixing the values with the flag.
+pub fn complete_inline_value(
+    name: &str,
+    prefix: &str,
+    mut result: CompleteResult,
+    params: &CompleteParams,
+) -> CompleteResult {
+    // The paths are listed here, as the hint would be applied to the whole
+    // arg.
+    if let Some(hint) = result.files.take() {
+        let paths = crate::path::complete_paths(prefix, &hint, params);
+        result.values.extend(paths);
+    }
+    for v in &mut result.values {
+        v.value = format!("{name}={}", v.value);
+    }
+    result
+}
+
+/// Finds the flag taking a value given by the arg (e.g. `--output`, `-o` or
+/// `-vo`), returning whether the value is given in the same arg (e.g.
+/// `--output=x` or `-ox`).
+fn find_flag<'a>(command: &'a clap::Command, arg: &str) -> Option<(&'a Arg, bool)> {
+    let takes_value = |a: &&Arg| a.get_action().takes_values();
+    if let Some(long) = arg.strip_prefix("--") {
+        let (name, value) = match long.split_once('=') {
+            Some((name, _)) => (name, true),
+            None => (long, false),
+        };
+        let flag = command.get_arguments().find(|a| {
+            a.get_long() == Some(name) || a.get_all_aliases().is_some_and(|v| v.contains(&name))
+        })?;
+        return Some((flag, value)).filter(|(f, _)| takes_value(f));
+    }
+    // The short flags can be grouped, the value follows the first flag that
+    // takes one.
+    let shorts = arg.strip_prefix('-')?;
+    for (i, c) in shorts.char_indices() {
+        let flag = command.get_arguments().find(|a| {
+            a.get_short() == Some(c) || a.get_all_short_aliases().is_some_and(|v| v.contains(&c))
+        })?;
+        if takes_value(&flag) {
+            return Some((flag, i + c.len_utf8() < shorts.len()));
+        }
+    }
+    None
+}
+
+/// The positional arg at the index, taking into account the args that take
+/// multiple values.
+fn positional_arg(command: &clap::Command, index: usize) -> Option<&Arg> {
+    let mut start: usize = 0;
+    for arg in command.get_positionals() {
+        let count = arg.get_num_args().map_or(1, |range| range.max_values());
+        if index < start.saturating_add(count) {
+            return Some(arg);
+        }
+        start = start.saturating_add(count);
+    }
+    None
+}
+
+fn flags(command: &clap::Command) -> impl Iterator<Item = CompletionValue> + '_ {
+    command
+        .get_arguments()
+        .filter(|a| !a.is_positional() && !a.is_hide_set())
+        .flat_map(|a| {
+            let longs = a
+                .get_long()
+                .into_iter()
+                .chain(a.get_visible_aliases().unwrap_or_default())
+                .map(|long| format!("--{long}"));
+            let shorts = a
+                .get_short()
+                .into_iter()
+                .chain(a.get_visible_short_aliases().unwrap_or_default())
+                .map(|short| format!("-{short}"));
+            let description = a.get_help().map(|help| help.to_string());
+            shorts.chain(longs).map(move |value| CompletionValue {
+                value,
+                description: description.clone(),
+                ..Default::default()
+            })
+        })
+}
+
+/// Completes the value of the arg with its possible values or its hint (the
+/// users and hosts are listed, the paths are left for the [FilesHint]).
+fn complete_value(arg: &Arg, prefix: &str, params: &CompleteParams) -> CompleteResult {
+    let mut values: Vec<CompletionValue> = arg
+        .get_possible_values()
+        .into_iter()
+        .filter(|v| !v.is_hide_set() && v.get_name().starts_with(prefix))
+        .map(|v| CompletionValue {
+            value: v.get_name().to_string(),
+            description: v.get_help().map(|help| help.to_string()),
+            ..Default::default()
+        })
+        .collect();
+    let provider = match arg.get_value_hint() {
+        ValueHint::Username => Some(Provider::Users),
+        ValueHint::Hostname => Some(Provider::Hosts),
+        _ => None,
+    };
+    if let Some(provider) = provider {
+        let provided = provider.values(params);
+        values.extend(provided.into_iter().filter(|v| v.value.starts_with(prefix)));
+    }
+    let files = match arg.get_value_hint() {
+        ValueHint::AnyPath | ValueHint::FilePath | ValueHint::ExecutablePath => {
+            Some(FilesHint::default())
+        }
+        ValueHint::DirPath => Some(FilesHint {
+            directories_only: true,
+            ..Default::default()
+        }),
+        _ => None,
+    };
+    CompleteResult {
+        values,
+        files,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::values;
+    use clap::{Parser, Subcommand, ValueEnum};
+    use googletest::prelude::*;
+    use std::path::PathBuf;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[arg(short, long, global = true)]
+        verbose: bool,
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    #[derive(Subcommand)]
+    enum Command {
+        /// Build the project.
+        #[command(alias = "b")]
+        Build {
+            /// The target platform.
+            #[arg(short, long)]
+            target: Option<Target>,
+            #[arg(long, value_hint = ValueHint::DirPath)]
+            out_dir: Option<PathBuf>,
+            files: Vec<PathBuf>,
+        },
+        #[command(hide = true)]
+        Internal,
+    }
+
+    #[derive(Clone, ValueEnum)]
+    enum Target {
+        Linux,
+        /// Windows (experimental).
+        Windows,
+    }
+
+    fn complete(args: &[&str]) -> CompleteResult {
+        let mut command = <Cli as clap::CommandFactory>::command();
+        command.build();
+        complete_command(
+            &command,
+            CompleteParams {
+                args: args.iter().map(|a| a.to_string()).collect(),
+                ..Default::default()
             },
-        ];
+        )
+        .unwrap()
+    }
+
+    #[gtest]
+    fn completes_subcommands_and_flags() {
+        let result = complete(&["cli", ""]);
+        expect_that!(
+            values(&result.values),
+            elements_are![eq("build"), eq("help")]
+        );
+        expect_that!(
+            result.values[0].description.as_deref(),
+            some(eq("Build the project"))
+        );
+        expect_that!(
+            values(&complete(&["cli", "-"]).values),
+            elements_are![eq("-v"), eq("--verbose"), eq("-h"), eq("--help")]
+        );
+        // The global flags are propagated to the subcommands.
+        expect_that!(
+            values(&complete(&["cli", "-v", "b", "--"]).values),
+            elements_are![
+                eq("--target"),
+                eq("--out-dir"),
+                eq("--verbose"),
+                eq("--help")
+            ]
+        );
+    }
+
+    #[gtest]
+    fn completes_values() {
+        let result = complete(&["cli", "build", "-t", ""]);
+        expect_that!(
+            values(&result.values),
+            elements_are![eq("linux"), eq("windows")]
+        );
+        expect_that!(
+            result.values[1].description.as_deref(),
+            some(eq("Windows (experimental)"))
+        );
+        expect_that!(
+            values(&complete(&["cli", "build", "-vt", "w"]).values),
+            elements_are![eq("windows")]
+        );
+        expect_that!(
+            values(&complete(&["cli", "build", "--target=l"]).values),
+            elements_are![eq("--target=linux")]
+        );
+        expect_that!(
+            complete(&["cli", "build", "--out-dir", ""]).files,
+            some(eq(&FilesHint {
+                directories_only: true,
+                ..Default::default()
+            }))
+        );
+        // The paths are inferred from the type of the arg.
+        expect_that!(
+            complete(&["cli", "build", "-tlinux", "a", ""]).files,
+            some(eq(&FilesHint::default()))
+        );
     }
-    Ok(CompleteResult {
-        values: completions,
-    })
 }
@@ -1,8 +1,10 @@
-use crate::types::{Message, Request, RequestId, Response};
+use crate::types::{Message, Notification, Request, RequestId, Response};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 use std::sync::mpsc::{Receiver, SyncSender};
 use std::sync::{Arc, Mutex};
 
@@ -29,7 +31,14 @@ struct ResponseCallback {
 // Internal state of the connection
 #[derive(Default)]
 struct ConnectionState {
-    responses: Mutex<HashMap<RequestId, ResponseCallback>>,
+    responses: Mutex<Responses>,
+}
+
+#[derive(Default)]
+struct Responses {
+    callbacks: HashMap<RequestId, ResponseCallback>,
+    // Set when no more responses will be received.
+    closed: bool,
 }
 
 #[derive(Clone)]
@@ -116,10 +125,6 @@ impl ConnectionSender {
         let method: String = method.into();
         let shutdown = method == "shutdown";
 
-        self.sender
-            .send(Request::new(id.clone(), method, params).into())
-            .map_err(|_| SendError {})?;
-
         let (tx, rx) = std::sync::mpsc::sync_channel(0);
 
         let callback = ResponseCallback {
@@ -137,10 +142,42 @@ impl ConnectionSender {
             shutdown,
         };
 
-        self.state.responses.lock().unwrap().insert(id, callback);
+        // The callback is registered before sending, so that the response can
+        // never arrive before it.
+        {
+            let mut responses = self.state.responses.lock().unwrap();
+            if responses.closed {
+                return Err(SendError {});
+            }
+            responses.callbacks.insert(id.clone(), callback);
+        }
+
+        if self
+            .sender
+            .send(Request::new(id.clone(), method, params).into())
+            .is_err()
+        {
+            self.state.responses.lock().unwrap().callbacks.remove(&id);
+            return Err(SendError {});
+        }
         Ok(ResponseHandle { receiver: rx })
     }
 
+    /// Sends the notification to the other side of the connection, which
+    /// doesn't respond to it.
+    pub fn notify(
+        &self,
+        method: impl Into<String>,
+        params: impl Serialize,
+    ) -> Result<(), SendError> {
+        if self.state.responses.lock().unwrap().closed {
+            return Err(SendError {});
+        }
+        self.sender
+            .send(Notification::new(method, params).into())
+            .map_err(|_| SendError {})
+    }
+
     /// Sends shutdown request to the other side.
     ///
     /// No new requests are allowed to be send after this call.
@@ -190,25 +227,53 @@ impl ConnRequest {
     }
 }
 
+/// The message received from the other side, that is not a response.
+pub enum Incoming {
+    Request(ConnRequest),
+    Notification(Notification),
+}
+
 impl ConnectionReceiver {
+    /// Returns the next request, ignoring the notifications (see
+    /// [Self::next_incoming]).
+    pub fn next_request(&self) -> Option<ConnRequest> {
+        loop {
+            match self.next_incoming()? {
+                Incoming::Request(req) => return Some(req),
+                Incoming::Notification(notification) => {
+                    log::debug!("ignoring notification {}", notification.method);
+                }
+            }
+        }
+    }
+
     // Note: This has to be called / polled continuously to ensure the
     // responses are populated
     // returns None when the connection is closed
-    pub fn next_request(&self) -> Option<ConnRequest> {
+    pub fn next_incoming(&self) -> Option<Incoming> {
         if *self.shutdown.lock().unwrap() {
             return None;
         }
         while let Ok(msg) = self.receiver.recv() {
             match msg {
                 Message::Request(req) => {
-                    return Some(ConnRequest {
+                    return Some(Incoming::Request(ConnRequest {
                         inner: req,
                         sender: self.sender.clone(),
-                    })
+                    }))
+                }
+                Message::Notification(notification) => {
+                    return Some(Incoming::Notification(notification))
                 }
                 Message::Response(res) => {
-                    let mut r = self.state.responses.lock().unwrap();
-                    let Some(callback) = r.remove(res.id()) else {
+                    let callback = self
+                        .state
+                        .responses
+                        .lock()
+                        .unwrap()
+                        .callbacks
+                        .remove(res.id());
+                    let Some(callback) = callback else {
                         log::warn!(
                             "Received response for id {:?}, but such request was never sent",
                             res.id()
@@ -224,6 +289,11 @@ impl ConnectionReceiver {
                 }
             }
         }
+        // The connection is closed, drop the pending callbacks so that the
+        // waiting response handles are notified.
+        let mut r = self.state.responses.lock().unwrap();
+        r.closed = true;
+        r.callbacks.clear();
         None
     }
 }
@@ -279,6 +349,16 @@ impl Transport {
         Self::raw(std::io::stdin(), std::io::stdout())
     }
 
+    /// Creates a transport over a connected unix socket.
+    ///
+    /// The write half of the socket is shut down once the transport stops
+    /// sending, so that the other side observes the end of the stream.
+    #[cfg(unix)]
+    pub fn unix(stream: UnixStream) -> std::io::Result<(Transport, JoinHandle)> {
+        let read = stream.try_clone()?;
+        Ok(Self::raw(read, ShutdownOnDrop(stream)))
+    }
+
     pub fn raw<R: Read + Send + 'static, W: Write + Send + 'static>(
         read: R,
         write: W,
@@ -319,6 +399,29 @@ impl Transport {
     }
 }
 
+#[cfg(unix)]
+struct ShutdownOnDrop(UnixStream);
+
+#[cfg(unix)]
+impl Write for ShutdownOnDrop {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ShutdownOnDrop {
+    fn drop(&mut self) {
+        if let Err(err) = self.0.shutdown(std::net::Shutdown::Write) {
+            log::debug!("failed to shutdown the socket: {err}");
+        }
+    }
+}
+
 fn read_loop<R: Read>(read: R, sender: SyncSender<Message>) -> anyhow::Result<()> {
     let reader = BufReader::new(read);
     for line in reader.lines() {
@@ -331,10 +434,7 @@ fn read_loop<R: Read>(read: R, sender: SyncSender<Message>) -> anyhow::Result<()
 }
 
 fn write_loop<W: Write>(mut write: W, receiver: Receiver<Message>) -> anyhow::Result<()> {
-    loop {
-        let Ok(msg) = receiver.recv() else {
-            break;
-        };
+    while let Ok(msg) = receiver.recv() {
         log::trace!("sending: {:?}", msg);
         let mut b = serde_json::to_vec(&msg)?;
         b.push(b'\n');
@@ -425,6 +525,36 @@ mod tests {
         let (t, join_handles) = Transport::raw(c, output);
         expect_that!(t.next_message(), some(anything()));
         expect_that!(t.next_message(), none());
+        // Drop, to ensure that the write loop finishes.
+        drop(t);
+        join_handles.join().unwrap();
+    }
+
+    #[test(gtest)]
+    fn receives_notifications() {
+        let input = [
+            json!({"method": "accepted", "params": {}}),
+            json!({"id": "1", "method": "complete", "params": {}}),
+            json!({"method": "accepted", "params": {}}),
+        ]
+        .iter()
+        .map(|m| format!("{m}\n"))
+        .collect::<String>();
+        let (t, join_handles) = Transport::raw(Cursor::new(input), Vec::new());
+        let (sender, receiver) = new_connection(t);
+        // The notifications are skipped when waiting for the requests.
+        expect_that!(
+            receiver.next_request().map(|r| r.inner().method.clone()),
+            some(eq("complete"))
+        );
+        let notification = match receiver.next_incoming() {
+            Some(Incoming::Notification(notification)) => Some(notification.method),
+            _ => None,
+        };
+        expect_that!(notification, some(eq("accepted")));
+        expect_that!(receiver.next_incoming().is_none(), eq(true));
+        drop(sender);
+        drop(receiver);
         join_handles.join().unwrap();
     }
 
@@ -447,4 +577,29 @@ mod tests {
         expect_that!(output, eq(&expected));
         join_handles.join().unwrap();
     }
+
+    #[test(gtest)]
+    fn pending_response_fails_when_connection_closes() {
+        let c = Cursor::new(vec![]);
+        let output: Vec<u8> = Vec::new();
+        let (t, join_handles) = Transport::raw(c, output);
+        let (sender, receiver) = new_connection(t);
+        let handle = sender
+            .send::<serde_json::Value>("complete", json!({}))
+            .unwrap();
+        expect_that!(receiver.next_request().is_none(), eq(true));
+        expect_that!(
+            handle.wait(),
+            err(matches_pattern!(ResponseError::ChannelClosed))
+        );
+        expect_that!(
+            sender
+                .send::<serde_json::Value>("complete", json!({}))
+                .is_err(),
+            eq(true)
+        );
+        drop(sender);
+        drop(receiver);
+        join_handles.join().unwrap();
+    }
 }
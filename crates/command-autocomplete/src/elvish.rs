@@ -0,0 +1,95 @@
+use crate::client::{CommandArgs, ServerArgs};
+use crate::shell_words::Dialect;
+use crate::types::CompletionValue;
+use clap::Args;
+use serde::Serialize;
+
+#[derive(Debug, Args)]
+pub struct ElvishArgs {
+    #[command(flatten)]
+    server: ServerArgs,
+    #[command(flatten)]
+    command: CommandArgs,
+}
+
+pub fn run_elvish(args: ElvishArgs) -> anyhow::Result<()> {
+    let (_, params) = args.command.into_params(Dialect::Elvish);
+    let values = crate::client::complete_in_shell(&args.server, params);
+    print!("{}", format_values(values));
+    Ok(())
+}
+
+/// The options of `edit:complex-candidate`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ComplexCandidate {
+    stem: String,
+    display: String,
+    code_suffix: String,
+}
+
+/// Formats the values as a stream of json objects (one per line), that can be
+/// read with `from-json` and passed to `edit:complex-candidate`.
+///
+/// The values are not quoted, as elvish quotes the candidates itself.
+fn format_values(values: Vec<CompletionValue>) -> String {
+    let mut out = String::new();
+    for v in values {
+        let display = v.display.as_deref().unwrap_or(&v.value);
+        let display = match &v.description {
+            Some(description) => format!("{} ({})", display, description),
+            None => display.to_string(),
+        };
+        // Elvish does not add a space after complex candidates by itself.
+        let code_suffix = if v.no_space || v.value.ends_with(char::is_whitespace) {
+            ""
+        } else {
+            " "
+        };
+        let candidate = ComplexCandidate {
+            stem: v.value,
+            display,
+            code_suffix: code_suffix.into(),
+        };
+        out.push_str(
+            &serde_json::to_string(&candidate).expect("serialization of candidates can't fail"),
+        );
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn formats_values() {
+        let values = vec![
+            CompletionValue {
+                value: "--verbose".into(),
+                description: Some("Use verbose output".into()),
+                ..Default::default()
+            },
+            CompletionValue {
+                value: "build ".into(),
+                description: None,
+                ..Default::default()
+            },
+            CompletionValue {
+                value: "with space \"quoted\"".into(),
+                description: Some("Special $chars".into()),
+                ..Default::default()
+            },
+            CompletionValue {
+                value: "src/".into(),
+                display: Some("src".into()),
+                no_space: true,
+                ..Default::default()
+            },
+        ];
+        let golden = include_str!("../testdata/elvish.golden");
+        expect_that!(format_values(values), eq(golden));
+    }
+}
@@ -0,0 +1,227 @@
+//! Filtering the values by the completed word (the last arg), so that they
+//! are matched the same way for all the completers. The parts of the values
+//! matched by the word are returned in [CompletionValue::matches].
+
+use crate::types::{CompletionValue, MatchRange};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterMode {
+    /// The values starting with the word.
+    #[default]
+    Prefix,
+    /// The values starting with the word, ignoring the case.
+    IgnoreCase,
+    /// The values containing the word (ignoring the case, unless the word has
+    /// uppercase letters).
+    Substring,
+    /// The values containing the characters of the word in order (ignoring
+    /// the case, unless the word has uppercase letters), the best matches
+    /// first.
+    Fuzzy,
+    /// The values are not filtered.
+    None,
+}
+
+impl FilterMode {
+    /// Whether the mode matches the values that don't start with the word.
+    pub fn beyond_prefix(self) -> bool {
+        matches!(
+            self,
+            FilterMode::IgnoreCase | FilterMode::Substring | FilterMode::Fuzzy
+        )
+    }
+}
+
+/// The part of the word needed by the completers to know what is completed
+/// (e.g. `--flag=`, `dir/` or the dashes of the flags). As the completers list
+/// only the values starting with the word, they get only this part for the
+/// modes matching [beyond the prefix](FilterMode::beyond_prefix).
+pub fn word_context(word: &str) -> &str {
+    match word.rfind(['/', '=']) {
+        Some(i) => &word[..=i],
+        None => &word[..word.len() - word.trim_start_matches('-').len()],
+    }
+}
+
+/// Keeps the values matching the word, setting their matched ranges. The
+/// fuzzy matches are sorted by their scores, unless the order has to be kept.
+pub fn filter(mode: FilterMode, word: &str, values: &mut Vec<CompletionValue>, keep_order: bool) {
+    if word.is_empty() || mode == FilterMode::None {
+        return;
+    }
+    let smart_case = !word.chars().any(char::is_uppercase);
+    let mut scored: Vec<(i64, CompletionValue)> = values
+        .drain(..)
+        .filter_map(|mut v| {
+            let (score, matches) = match mode {
+                FilterMode::Prefix => (0, vec![prefix(word, &v.value, false)?]),
+                FilterMode::IgnoreCase => (0, vec![prefix(word, &v.value, true)?]),
+                FilterMode::Substring => (0, vec![substring(word, &v.value, smart_case)?]),
+                FilterMode::Fuzzy => fuzzy(word, &v.value, smart_case)?,
+                FilterMode::None => unreachable!("checked above"),
+            };
+            v.matches = matches;
+            Some((score, v))
+        })
+        .collect();
+    if mode == FilterMode::Fuzzy && !keep_order {
+        // The sort is stable, so the equally good matches keep their order.
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    }
+    values.extend(scored.into_iter().map(|(_, v)| v));
+}
+
+/// Matches the word at the start of the value.
+fn prefix(word: &str, value: &str, ignore_case: bool) -> Option<MatchRange> {
+    let mut end = 0;
+    let mut value_chars = value.chars();
+    for w in word.chars() {
+        let c = value_chars.next()?;
+        if !chars_eq(w, c, ignore_case) {
+            return None;
+        }
+        end += c.len_utf8();
+    }
+    Some(MatchRange { start: 0, end })
+}
+
+/// Matches the first occurrence of the word in the value.
+fn substring(word: &str, value: &str, ignore_case: bool) -> Option<MatchRange> {
+    value.char_indices().find_map(|(start, _)| {
+        let range = prefix(word, &value[start..], ignore_case)?;
+        Some(MatchRange {
+            start,
+            end: start + range.end,
+        })
+    })
+}
+
+/// Matches the characters of the word in order, scoring the consecutive
+/// characters and the ones starting the words of the value (e.g. after `-`,
+/// `/` or in camelCase) higher, and the gaps lower.
+fn fuzzy(word: &str, value: &str, ignore_case: bool) -> Option<(i64, Vec<MatchRange>)> {
+    let mut ranges: Vec<MatchRange> = vec![];
+    let mut score = 0;
+    let mut value_chars = value.char_indices();
+    let mut prev: Option<char> = None;
+    for w in word.chars() {
+        loop {
+            let (i, c) = value_chars.next()?;
+            let boundary = match prev {
+                None => true,
+                Some(p) => !p.is_alphanumeric() || p.is_lowercase() && c.is_uppercase(),
+            };
+            prev = Some(c);
+            if !chars_eq(w, c, ignore_case) {
+                continue;
+            }
+            score += if boundary { 10 } else { 1 };
+            match ranges.last_mut() {
+                Some(range) if range.end == i => {
+                    range.end += c.len_utf8();
+                    score += 8;
+                }
+                last => {
+                    let gap = last.map_or(i, |range| i - range.end);
+                    score -= gap.min(5) as i64;
+                    ranges.push(MatchRange {
+                        start: i,
+                        end: i + c.len_utf8(),
+                    });
+                }
+            }
+            break;
+        }
+    }
+    Some((score, ranges))
+}
+
+fn chars_eq(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || ignore_case && a.to_lowercase().eq(b.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn filtered(
+        mode: FilterMode,
+        word: &str,
+        values: &[&str],
+    ) -> Vec<(String, Vec<(usize, usize)>)> {
+        let mut values = values
+            .iter()
+            .map(|v| CompletionValue {
+                value: v.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        filter(mode, word, &mut values, false);
+        values
+            .into_iter()
+            .map(|v| {
+                (
+                    v.value,
+                    v.matches.iter().map(|m| (m.start, m.end)).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn matched(value: &str, ranges: &[(usize, usize)]) -> (String, Vec<(usize, usize)>) {
+        (value.to_string(), ranges.to_vec())
+    }
+
+    #[gtest]
+    fn filters_by_prefix_and_substring() {
+        let values = ["--Verbose", "--version", "-v", "Cargo.toml"];
+        expect_that!(
+            filtered(FilterMode::Prefix, "--ver", &values),
+            elements_are![eq(&matched("--version", &[(0, 5)]))]
+        );
+        expect_that!(
+            filtered(FilterMode::IgnoreCase, "--ver", &values),
+            elements_are![
+                eq(&matched("--Verbose", &[(0, 5)])),
+                eq(&matched("--version", &[(0, 5)]))
+            ]
+        );
+        expect_that!(
+            filtered(FilterMode::Substring, "to", &values),
+            elements_are![eq(&matched("Cargo.toml", &[(6, 8)]))]
+        );
+        // The uppercase letters make the matching case sensitive.
+        expect_that!(
+            filtered(FilterMode::Substring, "V", &values),
+            elements_are![eq(&matched("--Verbose", &[(2, 3)]))]
+        );
+        expect_that!(filtered(FilterMode::None, "x", &values).len(), eq(4));
+        expect_that!(word_context("--out=src/ma"), eq("--out=src/"));
+        expect_that!(word_context("--ver"), eq("--"));
+        expect_that!(word_context("main"), eq(""));
+        expect_that!(filtered(FilterMode::Prefix, "", &values).len(), eq(4));
+    }
+
+    #[gtest]
+    fn ranks_fuzzy_matches() {
+        let values = ["feature/login-form", "fix-lint-flags", "main", "feat-lf"];
+        expect_that!(
+            filtered(FilterMode::Fuzzy, "flf", &values),
+            elements_are![
+                eq(&matched("feat-lf", &[(0, 1), (5, 7)])),
+                eq(&matched("fix-lint-flags", &[(0, 1), (4, 5), (9, 10)])),
+                eq(&matched("feature/login-form", &[(0, 1), (8, 9), (14, 15)]))
+            ]
+        );
+        expect_that!(
+            filtered(FilterMode::Fuzzy, "pAr", &["parser", "parseArgs", "pArg"]),
+            elements_are![
+                eq(&matched("pArg", &[(0, 3)])),
+                eq(&matched("parseArgs", &[(0, 1), (5, 7)]))
+            ]
+        );
+    }
+}
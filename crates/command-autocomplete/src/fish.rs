@@ -0,0 +1,116 @@
+use crate::shell_words::{self, Dialect};
+use crate::types::{CompleteParams, CompleteResult, CompletionValue, Error};
+use clap::Args;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Debug, Args)]
+pub struct FishArgs {
+    /// The fish binary to use.
+    #[arg(long, default_value = "fish")]
+    fish: PathBuf,
+}
+
+pub fn run_fish(args: FishArgs) -> anyhow::Result<()> {
+    crate::server::serve(|params| handle_complete_request(&args, params))
+}
+
+fn handle_complete_request(
+    args: &FishArgs,
+    params: CompleteParams,
+) -> Result<CompleteResult, Error> {
+    if params.args.is_empty() {
+        return Err(Error::invalid_request(
+            "params.args is empty, required at least one element",
+        ));
+    }
+
+    let line: Vec<_> = params
+        .args
+        .iter()
+        .map(|a| shell_words::quote(a, Dialect::Fish, None))
+        .collect();
+    let mut command = Command::new(&args.fish);
+    command.args(["-c", r#"complete --do-complete="$__cap_line""#]);
+    params.apply_to(&mut command);
+    // The line is passed in the environment, so that it's not interpreted by
+    // fish before the completion.
+    command.env("__cap_line", line.join(" "));
+    let output = command
+        .output()
+        .map_err(|e| Error::internal(format!("failed to run fish command: {e}")))?;
+    if !output.status.success() {
+        return Err(Error::internal(format!(
+            "fish command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(parse_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses the output of `complete --do-complete`, with a value per line,
+/// optionally followed by a tab and the description.
+pub fn parse_output(output: &str) -> CompleteResult {
+    CompleteResult {
+        values: output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (value, description) = match line.split_once('\t') {
+                    Some((value, description)) => (value, Some(description)),
+                    None => (line, None),
+                };
+                CompletionValue {
+                    // Fish does not report when the space should be skipped,
+                    // so we follow its rules for directories and options.
+                    no_space: value.ends_with('/') || value.ends_with('='),
+                    value: value.to_string(),
+                    description: description.filter(|d| !d.is_empty()).map(|d| d.to_string()),
+                    ..Default::default()
+                }
+            })
+            .collect(),
+        // Fish matches the values on its own (also by substrings and fuzzy),
+        // so these are not filtered again.
+        filtered: true,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn parses_output() {
+        let result = parse_output("--all\tShow all\nsrc/\n--color=\t\nmy file\tA file\n\n");
+        expect_that!(
+            result.values,
+            elements_are![
+                matches_pattern!(CompletionValue {
+                    value: eq("--all"),
+                    description: some(eq("Show all")),
+                    no_space: eq(&false),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("src/"),
+                    description: none(),
+                    no_space: eq(&true),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("--color="),
+                    description: none(),
+                    no_space: eq(&true),
+                }),
+                matches_pattern!(CompletionValue {
+                    value: eq("my file"),
+                    description: some(eq("A file")),
+                    no_space: eq(&false),
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! The values from the git repository of the working directory (branches,
+//! tags, remotes, stashes and changed files), read with the git plumbing
+//! commands. Outside of a repository (or without git) there are no values.
+
+use crate::types::{CompleteParams, CompletionValue};
+
+/// The local and remote branches, described by the subjects of their commits
+/// (and the upstreams of the local ones).
+pub fn branches(params: &CompleteParams) -> Vec<CompletionValue> {
+    let format = "--format=%(refname)%00%(refname:short)%00%(upstream:short)%00%(contents:subject)";
+    let Some(output) = git(
+        params,
+        &["for-each-ref", format, "refs/heads", "refs/remotes"],
+    ) else {
+        return vec![];
+    };
+    parse_refs(&output)
+}
+
+/// The tags, described by the subjects of their commits (or annotations).
+pub fn tags(params: &CompleteParams) -> Vec<CompletionValue> {
+    let format = "--format=%(refname)%00%(refname:short)%00%00%(contents:subject)";
+    let Some(output) = git(params, &["for-each-ref", format, "refs/tags"]) else {
+        return vec![];
+    };
+    parse_refs(&output)
+}
+
+/// The remotes, described by their urls.
+pub fn remotes(params: &CompleteParams) -> Vec<CompletionValue> {
+    let Some(output) = git(params, &["remote", "-v"]) else {
+        return vec![];
+    };
+    output
+        .lines()
+        .filter_map(|line| line.strip_suffix(" (fetch)")?.split_once('\t'))
+        .map(|(name, url)| CompletionValue {
+            value: name.to_string(),
+            description: Some(url.to_string()),
+            tag: Some("remotes".to_string()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// The stashes (`stash@{0}`, ...), described by their messages.
+pub fn stashes(params: &CompleteParams) -> Vec<CompletionValue> {
+    let Some(output) = git(params, &["stash", "list", "--format=%gd%x00%s"]) else {
+        return vec![];
+    };
+    output
+        .lines()
+        .filter_map(|line| line.split_once('\0'))
+        .map(|(name, message)| CompletionValue {
+            value: name.to_string(),
+            description: Some(message.to_string()),
+            tag: Some("stashes".to_string()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// The changed (and untracked) files, relative to the working directory and
+/// described by their status.
+pub fn changes(params: &CompleteParams) -> Vec<CompletionValue> {
+    let Some(prefix) = git(params, &["rev-parse", "--show-prefix"]) else {
+        return vec![];
+    };
+    let Some(output) = git(params, &["status", "--porcelain=v1", "-z"]) else {
+        return vec![];
+    };
+    parse_status(&output, prefix.trim_end_matches('\n'))
+}
+
+/// Runs git in the working directory of the request, returning its output
+/// when it succeeds.
+fn git(params: &CompleteParams, args: &[&str]) -> Option<String> {
+    let mut command = std::process::Command::new("git");
+    command.args(args);
+    params.apply_to(&mut command);
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(err) => {
+            log::debug!("failed to run git: {err}");
+            return None;
+        }
+    };
+    if !output.status.success() {
+        log::debug!(
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the `refname\0short\0upstream\0subject` lines.
+fn parse_refs(output: &str) -> Vec<CompletionValue> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\0');
+            let refname = fields.next()?;
+            let name = fields.next()?;
+            let upstream = fields.next().unwrap_or_default();
+            let subject = fields.next().unwrap_or_default();
+            let tag = if refname.starts_with("refs/heads/") {
+                "local branches"
+            } else if refname.starts_with("refs/remotes/") {
+                // The `origin/HEAD` symbolic refs are not branches.
+                if refname.ends_with("/HEAD") {
+                    return None;
+                }
+                "remote branches"
+            } else {
+                "tags"
+            };
+            Some(CompletionValue {
+                value: name.to_string(),
+                description: Some(match upstream {
+                    "" => subject.to_string(),
+                    upstream => format!("[{upstream}] {subject}"),
+                })
+                .filter(|d| !d.is_empty()),
+                tag: Some(tag.to_string()),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Parses the `XY path\0` entries (with the original path of the renames in
+/// the following entry), where the paths are relative to the root of the
+/// repository, and the prefix is the working directory in the repository.
+fn parse_status(output: &str, prefix: &str) -> Vec<CompletionValue> {
+    let mut values = vec![];
+    let mut entries = output.split('\0').filter(|e| !e.is_empty());
+    while let Some(entry) = entries.next() {
+        let (Some(status), Some(path)) = (entry.get(..2), entry.get(3..)) else {
+            continue;
+        };
+        if status.starts_with(['R', 'C']) {
+            entries.next();
+        }
+        values.push(CompletionValue {
+            value: relative_path(prefix, path),
+            description: Some(describe_status(status)),
+            tag: Some("changed files".to_string()),
+            ..Default::default()
+        });
+    }
+    values
+}
+
+fn describe_status(status: &str) -> String {
+    let (index, worktree) = {
+        let mut chars = status.chars();
+        (chars.next().unwrap_or(' '), chars.next().unwrap_or(' '))
+    };
+    if status == "??" {
+        return "untracked".to_string();
+    }
+    if index == 'U' || worktree == 'U' || status == "AA" || status == "DD" {
+        return "conflicted".to_string();
+    }
+    // The changes in the worktree are described before the staged ones.
+    let (change, staged) = match worktree {
+        ' ' => (index, true),
+        _ => (worktree, false),
+    };
+    let description = match change {
+        'M' => "modified",
+        'A' => "added",
+        'D' => "deleted",
+        'R' => "renamed",
+        'C' => "copied",
+        'T' => "type changed",
+        _ => "changed",
+    };
+    match staged {
+        true => format!("{description} (staged)"),
+        false => description.to_string(),
+    }
+}
+
+/// The path (relative to the root of the repository) relative to the
+/// directory given by the prefix (e.g. `src/`).
+fn relative_path(prefix: &str, path: &str) -> String {
+    let base: Vec<&str> = prefix.split('/').filter(|c| !c.is_empty()).collect();
+    let target: Vec<&str> = path.split('/').collect();
+    let common = base
+        .iter()
+        .zip(&target[..target.len() - 1])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = "../".repeat(base.len() - common);
+    relative.push_str(&target[common..].join("/"));
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{pair, pairs, TempDir};
+    use googletest::prelude::*;
+    use std::path::Path;
+
+    #[gtest]
+    fn parses_status() {
+        let output =
+            " M src/main.rs\0R  src/new.rs\0src/old.rs\0M  README.md\0UU src/lib.rs\0?? src/tmp/\0";
+        expect_that!(
+            pairs(parse_status(output, "src/")),
+            elements_are![
+                eq(&pair("main.rs", "modified")),
+                eq(&pair("new.rs", "renamed (staged)")),
+                eq(&pair("../README.md", "modified (staged)")),
+                eq(&pair("lib.rs", "conflicted")),
+                eq(&pair("tmp/", "untracked"))
+            ]
+        );
+        expect_that!(relative_path("", "a/b"), eq("a/b"));
+        expect_that!(relative_path("a/b/", "a/c/d"), eq("../c/d"));
+    }
+
+    #[gtest]
+    fn reads_repository() {
+        let dir = TempDir::new("git");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let run = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(&dir)
+                .stdout(std::process::Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?} failed");
+        };
+        run(&["init", "-q", "-b", "main"]);
+        std::fs::write(dir.join("src/main.rs"), "").unwrap();
+        run(&["add", "."]);
+        run(&["commit", "-q", "-m", "Initial commit"]);
+        run(&["tag", "v1"]);
+        run(&["branch", "feature"]);
+        run(&["remote", "add", "origin", "https://example.com/repo.git"]);
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        let params = CompleteParams {
+            args: vec![],
+            working_dir: Some(dir.join("src")),
+            ..Default::default()
+        };
+
+        let result = branches(&params);
+        expect_that!(result[0].tag.as_deref(), some(eq("local branches")));
+        expect_that!(
+            pairs(result),
+            elements_are![
+                eq(&pair("feature", "Initial commit")),
+                eq(&pair("main", "Initial commit"))
+            ]
+        );
+        expect_that!(
+            pairs(tags(&params)),
+            elements_are![eq(&pair("v1", "Initial commit"))]
+        );
+        expect_that!(
+            pairs(remotes(&params)),
+            elements_are![eq(&pair("origin", "https://example.com/repo.git"))]
+        );
+        expect_that!(stashes(&params), empty());
+        expect_that!(
+            pairs(changes(&params)),
+            elements_are![
+                eq(&pair("main.rs", "modified")),
+                eq(&pair("../notes.txt", "untracked"))
+            ]
+        );
+
+        let outside = CompleteParams {
+            args: vec![],
+            working_dir: Some(Path::new("/").to_path_buf()),
+            ..Default::default()
+        };
+        expect_that!(changes(&outside), empty());
+    }
+}
@@ -0,0 +1,550 @@
+use crate::cache::DiskCache;
+use crate::types::{CompleteParams, CompleteResult, CompletionValue, Error, FilesHint};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Args)]
+pub struct HelpArgs {
+    /// How long to wait for the help of a command, in milliseconds.
+    #[arg(long, default_value_t = 3000)]
+    timeout_ms: u64,
+    /// Do not cache the parsed help on disk.
+    #[arg(long)]
+    no_cache: bool,
+}
+
+pub fn run_help(args: HelpArgs) -> anyhow::Result<()> {
+    let completer = HelpCompleter {
+        timeout: Duration::from_millis(args.timeout_ms),
+        cache: (!args.no_cache).then(|| DiskCache::new("help")),
+    };
+    crate::server::serve(|params| completer.complete(params))
+}
+
+/// The subcommands and flags of a command, parsed from its help.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+struct HelpPage {
+    commands: Vec<HelpCommand>,
+    flags: Vec<HelpFlag>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct HelpCommand {
+    name: String,
+    description: Option<String>,
+}
+
+/// A flag of a command, also used by the other bridges that parse the
+/// documentation.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HelpFlag {
+    pub names: Vec<String>,
+    /// Whether the flag takes its value as the next arg (when not given with
+    /// `=`).
+    pub takes_value: bool,
+    pub description: Option<String>,
+}
+
+struct HelpCompleter {
+    timeout: Duration,
+    cache: Option<DiskCache>,
+}
+
+impl HelpCompleter {
+    fn complete(&self, params: CompleteParams) -> Result<CompleteResult, Error> {
+        let Some((current, args)) = params.args.split_last() else {
+            return Err(Error::invalid_request(
+                "params.args is empty, required at least one element",
+            ));
+        };
+        if args.is_empty() {
+            return Ok(CompleteResult::default());
+        }
+        let program = params
+            .find_program(&args[0])
+            .ok_or_else(|| Error::invalid_request(format!("{} not found", args[0])))?;
+
+        let mut subcommands = vec![];
+        let mut page = self.help_page(&program, &subcommands, &params)?;
+        let mut flag_value = false;
+        let mut positional_only = false;
+        for arg in &args[1..] {
+            if flag_value {
+                flag_value = false;
+            } else if positional_only {
+                continue;
+            } else if arg == "--" {
+                positional_only = true;
+            } else if arg.starts_with('-') {
+                flag_value = !arg.contains('=') && page.flag(arg).is_some_and(|f| f.takes_value);
+            } else if page.commands.iter().any(|c| c.name == *arg) {
+                subcommands.push(arg.as_str());
+                page = self.help_page(&program, &subcommands, &params)?;
+            }
+        }
+
+        if flag_value {
+            // The value of the flag is unknown, the files are the best guess.
+            return Ok(CompleteResult {
+                files: Some(FilesHint::default()),
+                ..Default::default()
+            });
+        }
+        if current.starts_with('-') && !positional_only {
+            return Ok(CompleteResult {
+                values: flag_values(&page.flags, current),
+                ..Default::default()
+            });
+        }
+        if page.commands.is_empty() || positional_only {
+            return Ok(CompleteResult {
+                files: Some(FilesHint::default()),
+                ..Default::default()
+            });
+        }
+        Ok(CompleteResult {
+            values: page
+                .commands
+                .iter()
+                .filter(|c| c.name.starts_with(current.as_str()))
+                .map(|c| CompletionValue {
+                    value: c.name.clone(),
+                    description: c.description.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the parsed help of the subcommand, from the cache when the
+    /// program did not change.
+    fn help_page(
+        &self,
+        program: &Path,
+        subcommands: &[&str],
+        params: &CompleteParams,
+    ) -> Result<HelpPage, Error> {
+        let key =
+            crate::cache::file_key(program).map(|key| format!("{key} {}", subcommands.join(" ")));
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some(page) = cache.get(key) {
+                return Ok(page);
+            }
+        }
+
+        let mut command = Command::new(program);
+        command.args(subcommands).arg("--help");
+        params.apply_to(&mut command);
+        // Asks for the help without colors and wrapped as little as possible.
+        command.env("NO_COLOR", "1").env("COLUMNS", "1000");
+        let page = parse_help(&self.run(command)?);
+
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            cache.put(key, &page);
+        }
+        Ok(page)
+    }
+
+    /// Runs the command and returns its output (stderr when stdout is empty),
+    /// killing it after the timeout.
+    fn run(&self, mut command: Command) -> Result<String, Error> {
+        log::debug!("getting help with {:?}", command);
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::internal(format!("failed to run {:?}: {e}", command)))?;
+        let read_all = |mut r: Box<dyn Read + Send>| {
+            std::thread::spawn(move || {
+                let mut buf = vec![];
+                let _ = r.read_to_end(&mut buf);
+                buf
+            })
+        };
+        let stdout = read_all(Box::new(child.stdout.take().expect("stdout is piped")));
+        let stderr = read_all(Box::new(child.stderr.take().expect("stderr is piped")));
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) => break,
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                Ok(None) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(Error::internal(format!(
+                        "{:?} did not finish in {}ms",
+                        command,
+                        self.timeout.as_millis()
+                    )));
+                }
+                Err(e) => return Err(Error::internal(format!("failed to wait for help: {e}"))),
+            }
+        }
+        let stdout = stdout.join().unwrap_or_default();
+        let output = if stdout.iter().all(|b| b.is_ascii_whitespace()) {
+            stderr.join().unwrap_or_default()
+        } else {
+            stdout
+        };
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+impl HelpPage {
+    fn flag(&self, arg: &str) -> Option<&HelpFlag> {
+        self.flags.iter().find(|f| f.names.iter().any(|n| n == arg))
+    }
+}
+
+#[derive(PartialEq)]
+enum Section {
+    Commands,
+    Other,
+}
+
+/// What the last entry was, so that the more indented lines after it can be
+/// attributed to it.
+enum LastEntry {
+    Flag(usize),
+    Command(usize),
+    /// The subcommands of argparse (`{build,run}`), listed below it, and the
+    /// one described by the last line.
+    Choices {
+        names: Vec<String>,
+        current: Option<usize>,
+    },
+    Other,
+}
+
+/// Parses the help in the common formats (clap, argparse, GNU getopt and
+/// cobra). The flags are found anywhere in the help, the subcommands in the
+/// sections with "command" in the name.
+fn parse_help(help: &str) -> HelpPage {
+    let help = strip_ansi(help);
+    let mut page = HelpPage::default();
+    let mut section = Section::Other;
+    let mut last: Option<(usize, LastEntry)> = None;
+    for line in help.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        if indent == 0 {
+            section = match trimmed.strip_suffix(':') {
+                Some(header) if header.to_lowercase().contains("command") => Section::Commands,
+                _ => Section::Other,
+            };
+            last = None;
+            continue;
+        }
+        let (spec, description) = split_columns(trimmed);
+
+        // The long flags without the short ones are often more indented, so
+        // the flags are never taken as a continuation of the last entry.
+        if let Some((last_indent, entry)) = last.as_mut().filter(|_| !trimmed.starts_with('-')) {
+            if indent > *last_indent {
+                let described = match entry {
+                    LastEntry::Choices { names, current } if names.iter().any(|n| n == spec) => {
+                        *current = page.commands.iter().position(|c| c.name == spec);
+                        if let Some(i) = *current {
+                            page.commands[i].description = description;
+                        }
+                        None
+                    }
+                    LastEntry::Choices { current, .. } => {
+                        current.map(|i| &mut page.commands[i].description)
+                    }
+                    LastEntry::Flag(i) => Some(&mut page.flags[*i].description),
+                    LastEntry::Command(i) => Some(&mut page.commands[*i].description),
+                    LastEntry::Other => None,
+                };
+                // The descriptions wrapped on several lines are joined.
+                match described {
+                    Some(Some(d)) => {
+                        d.push(' ');
+                        d.push_str(trimmed);
+                    }
+                    Some(d) => *d = Some(trimmed.to_string()),
+                    None => {}
+                }
+                continue;
+            }
+        }
+
+        let entry = if trimmed.starts_with('-') {
+            match parse_flag(spec) {
+                Some((names, takes_value)) => {
+                    page.flags.push(HelpFlag {
+                        names,
+                        takes_value,
+                        description,
+                    });
+                    LastEntry::Flag(page.flags.len() - 1)
+                }
+                None => LastEntry::Other,
+            }
+        } else if let Some(choices) = spec.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            let names: Vec<_> = choices.split(',').map(|c| c.trim().to_string()).collect();
+            for name in names.iter().filter(|n| is_command_name(n)) {
+                page.commands.push(HelpCommand {
+                    name: name.clone(),
+                    description: None,
+                });
+            }
+            LastEntry::Choices {
+                names,
+                current: None,
+            }
+        } else if section == Section::Commands {
+            let name = spec.split([',', ' ']).next().unwrap_or_default();
+            if is_command_name(name) {
+                page.commands.push(HelpCommand {
+                    name: name.to_string(),
+                    description,
+                });
+                LastEntry::Command(page.commands.len() - 1)
+            } else {
+                LastEntry::Other
+            }
+        } else {
+            LastEntry::Other
+        };
+        last = Some((indent, entry));
+    }
+    page
+}
+
+/// Returns the names of the flags starting with the prefix.
+pub fn flag_values(flags: &[HelpFlag], prefix: &str) -> Vec<CompletionValue> {
+    flags
+        .iter()
+        .flat_map(|flag| flag.names.iter().map(move |name| (name, flag)))
+        .filter(|(name, _)| name.starts_with(prefix))
+        .map(|(name, flag)| CompletionValue {
+            value: name.clone(),
+            description: flag.description.clone(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Splits the line into the entry and its description, separated by at least
+/// two spaces (or a tab).
+fn split_columns(line: &str) -> (&str, Option<String>) {
+    let separator = [line.find("  "), line.find('\t')]
+        .into_iter()
+        .flatten()
+        .min();
+    match separator {
+        Some(i) => {
+            let description = line[i..].trim();
+            (
+                &line[..i],
+                Some(description.to_string()).filter(|d| !d.is_empty()),
+            )
+        }
+        None => (line, None),
+    }
+}
+
+/// Parses the names of the flag, e.g. `-c, --config <FILE>` or
+/// `--block-size=SIZE`, and whether it takes a value.
+pub fn parse_flag(spec: &str) -> Option<(Vec<String>, bool)> {
+    let mut names = vec![];
+    let mut takes_value = false;
+    for token in spec.split([',', ' ']).filter(|t| !t.is_empty()) {
+        if let Some(rest) = token.strip_prefix('-') {
+            let end = token.find(['=', '[', '<']).unwrap_or(token.len());
+            let (name, value) = token.split_at(end);
+            let name = name.trim_end_matches("...");
+            if !rest.is_empty()
+                && name != "--"
+                && name
+                    .trim_start_matches('-')
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            {
+                names.push(name.to_string());
+            }
+            // `--color[=WHEN]` takes an optional value, only with `=`.
+            if value.starts_with('=') || value.starts_with('<') {
+                takes_value = true;
+            }
+        } else if !token.starts_with('[') && !token.starts_with("...") {
+            // The value placeholder, e.g. `<FILE>`, `FILE` or `string`.
+            takes_value = true;
+        }
+    }
+    (!names.is_empty()).then_some((names, takes_value))
+}
+
+fn is_command_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Removes the ANSI escape sequences (colors), that some CLIs print even when
+/// not connected to a terminal.
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn command(name: &str, description: &str) -> HelpCommand {
+        HelpCommand {
+            name: name.into(),
+            description: Some(description.into()),
+        }
+    }
+
+    fn flag(names: &[&str], takes_value: bool, description: &str) -> HelpFlag {
+        HelpFlag {
+            names: names.iter().map(|n| n.to_string()).collect(),
+            takes_value,
+            description: Some(description.into()),
+        }
+    }
+
+    #[gtest]
+    fn parses_clap_help() {
+        let page = parse_help(include_str!("../testdata/help/clap.txt"));
+        expect_that!(
+            page.commands,
+            elements_are![
+                eq(&command("build", "Build the project")),
+                eq(&command("run", "Run the project [aliases: r]")),
+                eq(&command(
+                    "help",
+                    "Print this message or the help of the given subcommand(s)"
+                )),
+            ]
+        );
+        expect_that!(
+            page.flags,
+            elements_are![
+                eq(&flag(&["-c", "--config"], true, "The configuration file")),
+                eq(&flag(&["-v", "--verbose"], false, "Increase the verbosity")),
+                eq(&flag(&["--color"], false, "When to use colors")),
+                eq(&flag(&["-h", "--help"], false, "Print help")),
+                eq(&flag(&["-V", "--version"], false, "Print version")),
+            ]
+        );
+    }
+
+    #[gtest]
+    fn parses_argparse_help() {
+        let page = parse_help(include_str!("../testdata/help/argparse.txt"));
+        expect_that!(
+            page.commands,
+            elements_are![
+                eq(&command("build", "build the project")),
+                eq(&command("run", "run the project")),
+            ]
+        );
+        expect_that!(
+            page.flags,
+            elements_are![
+                eq(&flag(
+                    &["-h", "--help"],
+                    false,
+                    "show this help message and exit"
+                )),
+                eq(&flag(&["--jobs", "-j"], true, "number of jobs")),
+                eq(&flag(&["--dry-run"], false, "only print the commands")),
+            ]
+        );
+    }
+
+    #[gtest]
+    fn parses_gnu_help() {
+        let page = parse_help(include_str!("../testdata/help/gnu.txt"));
+        expect_that!(page.commands, empty());
+        expect_that!(
+            page.flags,
+            elements_are![
+                eq(&flag(
+                    &["-a", "--all"],
+                    false,
+                    "do not ignore entries starting with ."
+                )),
+                eq(&flag(
+                    &["--block-size"],
+                    true,
+                    "with -l, scale sizes by SIZE when printing them; \
+                     e.g., '--block-size=M'; see SIZE format below"
+                )),
+                eq(&flag(
+                    &["--color"],
+                    false,
+                    "color the output WHEN; more info below"
+                )),
+                eq(&flag(
+                    &["-w", "--width"],
+                    true,
+                    "set output width to COLS.  0 means no limit"
+                )),
+                eq(&flag(&["--help"], false, "display this help and exit")),
+            ]
+        );
+    }
+
+    #[gtest]
+    fn parses_cobra_help() {
+        let page = parse_help(include_str!("../testdata/help/cobra.txt"));
+        expect_that!(
+            page.commands,
+            elements_are![
+                eq(&command("build", "Build the project")),
+                eq(&command(
+                    "completion",
+                    "Generate the autocompletion script for the specified shell"
+                )),
+                eq(&command("run", "Run the project")),
+            ]
+        );
+        expect_that!(
+            page.flags,
+            elements_are![
+                eq(&flag(&["-h", "--help"], false, "help for mytool")),
+                eq(&flag(&["-o", "--output"], true, "output format")),
+                eq(&flag(&["--quiet"], false, "don't print anything")),
+                eq(&flag(
+                    &["--config"],
+                    true,
+                    "config file (default is $HOME/.mytool.yaml)"
+                )),
+            ]
+        );
+    }
+}
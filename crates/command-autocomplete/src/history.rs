@@ -0,0 +1,272 @@
+//! The history of the accepted completion values (in
+//! `$XDG_DATA_HOME/command-autocomplete/history.json`), used by the router to
+//! list the values inserted often and recently first.
+//!
+//! Like the [crate::cache::DiskCache], the history is best effort: failing to
+//! read or write it is not an error. The values recorded by the threads of a
+//! process are serialized, but the ones recorded concurrently by several
+//! processes may be lost (the last write wins).
+
+use crate::cache::write_atomic;
+use crate::system::{data_home, now};
+use crate::types::{AcceptedParams, CompleteParams, CompletionValue};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The number of entries kept, the lowest scored ones are dropped first.
+const MAX_ENTRIES: usize = 1000;
+/// The time (in seconds) after which the score of an entry halves.
+const HALF_LIFE: f64 = 14.0 * 24.0 * 60.0 * 60.0;
+/// The multiplier of the score of the values accepted in the same directory.
+const SAME_DIR_BOOST: f64 = 2.0;
+
+#[derive(Clone, Debug)]
+pub struct History {
+    path: Option<PathBuf>,
+    /// Held while recording, so that the threads don't overwrite the entries
+    /// recorded by each other.
+    lock: Arc<Mutex<()>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct Entry {
+    /// The args before the completed word (the program with its subcommands
+    /// and the preceding args), so that the values accepted for a subcommand
+    /// don't rank the values of the others.
+    command: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    working_dir: Option<PathBuf>,
+    value: String,
+    count: u32,
+    /// The time of the last use, in seconds since the unix epoch.
+    last_used: u64,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            path: data_home().map(|dir| dir.join("command-autocomplete/history.json")),
+            lock: Default::default(),
+        }
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        History {
+            path: Some(path),
+            lock: Default::default(),
+        }
+    }
+
+    /// Records the accepted value, for the command (the args before the
+    /// completed word) and the working directory of the completion.
+    pub fn record(&self, params: &AcceptedParams) {
+        let Some((_, command)) = params.args.split_last() else {
+            return;
+        };
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let now = now();
+        let mut entries = self.load();
+        match entries.iter_mut().find(|e| {
+            e.command == *command && e.working_dir == params.working_dir && e.value == params.value
+        }) {
+            Some(entry) => {
+                entry.count = entry.count.saturating_add(1);
+                entry.last_used = now;
+            }
+            None => entries.push(Entry {
+                command: command.to_vec(),
+                working_dir: params.working_dir.clone(),
+                value: params.value.clone(),
+                count: 1,
+                last_used: now,
+            }),
+        }
+        if entries.len() > MAX_ENTRIES {
+            entries.sort_by(|a, b| score(b, None, now).total_cmp(&score(a, None, now)));
+            entries.truncate(MAX_ENTRIES);
+        }
+        self.save(&entries);
+    }
+
+    /// Orders the values by their scores (the number of times they were
+    /// accepted for the command, decaying with time, and boosted in the same
+    /// directory). The values without history keep their order, after the
+    /// ones with history.
+    pub fn rank(&self, params: &CompleteParams, values: &mut Vec<CompletionValue>) {
+        let Some((_, command)) = params.args.split_last() else {
+            return;
+        };
+        let entries: Vec<Entry> = self
+            .load()
+            .into_iter()
+            .filter(|e| e.command == *command)
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+        let now = now();
+        let working_dir = params.working_dir.as_deref();
+        let mut scored: Vec<(f64, CompletionValue)> = values
+            .drain(..)
+            .map(|v| {
+                let score = entries
+                    .iter()
+                    .filter(|e| e.value == v.value)
+                    .map(|e| score(e, working_dir, now))
+                    .sum();
+                (score, v)
+            })
+            .collect();
+        // The sort is stable, so the values with equal scores keep the order
+        // of the completer.
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        values.extend(scored.into_iter().map(|(_, v)| v));
+    }
+
+    fn load(&self) -> Vec<Entry> {
+        let Some(path) = &self.path else {
+            return vec![];
+        };
+        let Ok(data) = std::fs::read(path) else {
+            return vec![];
+        };
+        match serde_json::from_slice(&data) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("ignoring invalid history {}: {err}", path.display());
+                vec![]
+            }
+        }
+    }
+
+    fn save(&self, entries: &[Entry]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_vec(entries)
+            .map_err(std::io::Error::from)
+            .and_then(|data| write_atomic(path, &data));
+        if let Err(err) = result {
+            log::warn!("failed to write history {}: {err}", path.display());
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn score(entry: &Entry, working_dir: Option<&Path>, now: u64) -> f64 {
+    let age = now.saturating_sub(entry.last_used) as f64;
+    let mut score = entry.count as f64 * 0.5f64.powf(age / HALF_LIFE);
+    if working_dir.is_some() && entry.working_dir.as_deref() == working_dir {
+        score *= SAME_DIR_BOOST;
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use googletest::prelude::*;
+
+    fn accepted(value: &str, working_dir: &str) -> AcceptedParams {
+        AcceptedParams {
+            args: vec!["git".into(), "checkout".into(), "".into()],
+            value: value.into(),
+            working_dir: Some(working_dir.into()),
+        }
+    }
+
+    fn rank(history: &History, command: &str, working_dir: &str) -> Vec<String> {
+        let params = CompleteParams {
+            args: command.split(' ').chain([""]).map(String::from).collect(),
+            working_dir: Some(working_dir.into()),
+            ..Default::default()
+        };
+        let mut values = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|v| CompletionValue {
+                value: v.into(),
+                ..Default::default()
+            })
+            .collect();
+        history.rank(&params, &mut values);
+        values.into_iter().map(|v| v.value).collect()
+    }
+
+    #[gtest]
+    fn ranks_accepted_values() {
+        let dir = TempDir::new("history");
+        let history = History::at(dir.join("history.json"));
+
+        expect_that!(
+            rank(&history, "git checkout", "/src"),
+            elements_are![eq("a"), eq("b"), eq("c"), eq("d")]
+        );
+        history.record(&accepted("d", "/src"));
+        history.record(&accepted("c", "/src"));
+        history.record(&accepted("c", "/src"));
+        history.record(&accepted("b", "/other"));
+        expect_that!(
+            rank(&history, "git checkout", "/src"),
+            elements_are![eq("c"), eq("d"), eq("b"), eq("a")]
+        );
+        // The values accepted in the same directory are boosted.
+        expect_that!(
+            rank(&history, "git checkout", "/other"),
+            elements_are![eq("b"), eq("c"), eq("d"), eq("a")]
+        );
+        expect_that!(
+            rank(&history, "cargo", "/src"),
+            elements_are![eq("a"), eq("b"), eq("c"), eq("d")]
+        );
+        // The subcommands of the same program don't share the history.
+        expect_that!(
+            rank(&history, "git add", "/src"),
+            elements_are![eq("a"), eq("b"), eq("c"), eq("d")]
+        );
+        expect_that!(
+            rank(&history, "git", "/src"),
+            elements_are![eq("a"), eq("b"), eq("c"), eq("d")]
+        );
+    }
+
+    #[gtest]
+    fn records_from_several_threads() {
+        let dir = TempDir::new("history-threads");
+        let history = History::at(dir.join("history.json"));
+
+        let threads: Vec<_> = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|value| {
+                let history = history.clone();
+                std::thread::spawn(move || history.record(&accepted(value, "/src")))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        expect_that!(history.load().len(), eq(4));
+    }
+
+    #[gtest]
+    fn decays_old_entries() {
+        let entry = Entry {
+            command: vec!["git".into()],
+            working_dir: None,
+            value: "main".into(),
+            count: 4,
+            last_used: 1_000_000,
+        };
+        expect_that!(score(&entry, None, 1_000_000), eq(4.0));
+        expect_that!(
+            score(&entry, None, 1_000_000 + HALF_LIFE as u64 * 2),
+            eq(1.0)
+        );
+    }
+}
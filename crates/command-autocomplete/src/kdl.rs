@@ -0,0 +1,465 @@
+//! A parser of the subset of [KDL](https://kdl.dev) (both v1 and v2) needed to
+//! read the usage specs: nodes with args, props and children, strings (quoted,
+//! raw and multi-line), keywords and comments. Type annotations are skipped,
+//! numbers are kept as strings.
+
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub args: Vec<Value>,
+    pub props: Vec<(String, Value)>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Number(String),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Node {
+    /// The arg at the index, if it is a string.
+    pub fn arg(&self, index: usize) -> Option<&str> {
+        self.args.get(index).and_then(Value::as_str)
+    }
+
+    /// The last value of the prop (as the later props override the earlier).
+    pub fn prop(&self, name: &str) -> Option<&Value> {
+        self.props
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    pub fn prop_str(&self, name: &str) -> Option<&str> {
+        self.prop(name).and_then(Value::as_str)
+    }
+
+    pub fn prop_bool(&self, name: &str) -> bool {
+        self.prop(name) == Some(&Value::Bool(true))
+    }
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Vec<Node>, ParseError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+    };
+    let nodes = parser.nodes()?;
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected '}'"));
+    }
+    Ok(nodes)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+/// The characters that can't be used in the identifiers.
+const NON_IDENTIFIER: &str = "(){}[]/\\\"#;=";
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let end = self.pos.min(self.chars.len());
+        ParseError {
+            line: self.chars[..end].iter().filter(|c| **c == '\n').count() + 1,
+            message: message.into(),
+        }
+    }
+
+    /// Parses the nodes until the end of the input or the closing brace
+    /// (which is not consumed).
+    fn nodes(&mut self) -> Result<Vec<Node>, ParseError> {
+        let mut nodes = vec![];
+        loop {
+            self.skip_whitespace(true)?;
+            match self.peek() {
+                None | Some('}') => return Ok(nodes),
+                Some(';') => self.pos += 1,
+                _ if self.starts_with("/-") => {
+                    self.pos += 2;
+                    self.skip_whitespace(true)?;
+                    self.node()?;
+                }
+                _ => nodes.push(self.node()?),
+            }
+        }
+    }
+
+    fn node(&mut self) -> Result<Node, ParseError> {
+        self.skip_type()?;
+        let mut node = Node {
+            name: match self.value()? {
+                Value::String(name) => name,
+                _ => return Err(self.error("expected a node name")),
+            },
+            ..Default::default()
+        };
+        loop {
+            self.skip_whitespace(false)?;
+            let discard = self.starts_with("/-");
+            if discard {
+                self.pos += 2;
+                self.skip_whitespace(false)?;
+            }
+            match self.peek() {
+                None | Some('\n' | ';' | '}') => return Ok(node),
+                Some('{') => {
+                    self.pos += 1;
+                    let children = self.nodes()?;
+                    if self.peek() != Some('}') {
+                        return Err(self.error("expected '}'"));
+                    }
+                    self.pos += 1;
+                    if !discard {
+                        node.children = children;
+                        return Ok(node);
+                    }
+                }
+                _ => {
+                    self.skip_type()?;
+                    let value = self.value()?;
+                    match value {
+                        Value::String(name) if self.peek() == Some('=') => {
+                            self.pos += 1;
+                            self.skip_type()?;
+                            let value = self.value()?;
+                            if !discard {
+                                node.props.push((name, value));
+                            }
+                        }
+                        value if !discard => node.args.push(value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Skips the whitespace and comments, including the newlines if
+    /// `newlines` is set (otherwise only the escaped ones).
+    fn skip_whitespace(&mut self, newlines: bool) -> Result<(), ParseError> {
+        while let Some(c) = self.peek() {
+            if c == '\n' && newlines || c != '\n' && c.is_whitespace() || c == '\u{feff}' {
+                self.pos += 1;
+            } else if self.starts_with("//") {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else if self.starts_with("/*") {
+                self.skip_block_comment()?;
+            } else if c == '\\' {
+                // A line continuation.
+                self.pos += 1;
+                self.skip_whitespace(false)?;
+                if self.peek() == Some('\n') {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), ParseError> {
+        let mut depth = 0;
+        loop {
+            if self.starts_with("/*") {
+                depth += 1;
+                self.pos += 2;
+            } else if self.starts_with("*/") {
+                depth -= 1;
+                self.pos += 2;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if self.peek().is_some() {
+                self.pos += 1;
+            } else {
+                return Err(self.error("unterminated comment"));
+            }
+        }
+    }
+
+    fn skip_type(&mut self) -> Result<(), ParseError> {
+        if self.peek() == Some('(') {
+            while self.peek().is_some_and(|c| c != ')') {
+                self.pos += 1;
+            }
+            if self.peek().is_none() {
+                return Err(self.error("unterminated type annotation"));
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => self.string().map(Value::String),
+            Some('r') if matches!(self.chars.get(self.pos + 1), Some('"' | '#')) => {
+                self.pos += 1;
+                self.raw_string().map(Value::String)
+            }
+            Some('#') if matches!(self.chars.get(self.pos + 1), Some('"' | '#')) => {
+                self.raw_string().map(Value::String)
+            }
+            Some('#') => {
+                self.pos += 1;
+                match self.identifier().as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Null),
+                    // The numbers like #inf and #nan.
+                    keyword => Ok(Value::Number(format!("#{keyword}"))),
+                }
+            }
+            Some(_) => {
+                let identifier = self.identifier();
+                let mut chars = identifier.chars();
+                let first = chars.next();
+                let is_number = first.is_some_and(|c| c.is_ascii_digit())
+                    || matches!(first, Some('+' | '-' | '.'))
+                        && chars.next().is_some_and(|c| c.is_ascii_digit());
+                match identifier.as_str() {
+                    "" => Err(self.error(format!("unexpected {:?}", self.peek().unwrap()))),
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    "null" => Ok(Value::Null),
+                    _ if is_number => Ok(Value::Number(identifier)),
+                    _ => Ok(Value::String(identifier)),
+                }
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn identifier(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !NON_IDENTIFIER.contains(c))
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        if self.starts_with("\"\"\"") {
+            self.pos += 3;
+            let start = self.pos;
+            while !self.starts_with("\"\"\"") {
+                if self.peek().is_none() {
+                    return Err(self.error("unterminated string"));
+                }
+                self.pos += if self.peek() == Some('\\') { 2 } else { 1 };
+            }
+            let raw: String = self.chars[start..self.pos].iter().collect();
+            self.pos += 3;
+            return Ok(dedent(&unescape(&raw)));
+        }
+        self.pos += 1;
+        let start = self.pos;
+        while self.peek() != Some('"') {
+            if self.peek().is_none() {
+                return Err(self.error("unterminated string"));
+            }
+            self.pos += if self.peek() == Some('\\') { 2 } else { 1 };
+        }
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        self.pos += 1;
+        Ok(unescape(&raw))
+    }
+
+    /// Parses `#"..."#` (or `"..."` after the `r` in KDL v1), with any number
+    /// of hashes.
+    fn raw_string(&mut self) -> Result<String, ParseError> {
+        let mut hashes = 0;
+        while self.peek() == Some('#') {
+            hashes += 1;
+            self.pos += 1;
+        }
+        let multiline = self.starts_with("\"\"\"");
+        let quotes = if multiline { "\"\"\"" } else { "\"" };
+        if !self.starts_with(quotes) {
+            return Err(self.error("expected '\"'"));
+        }
+        self.pos += quotes.len();
+        let end = format!("{quotes}{}", "#".repeat(hashes));
+        let start = self.pos;
+        while !self.starts_with(&end) {
+            if self.peek().is_none() {
+                return Err(self.error("unterminated string"));
+            }
+            self.pos += 1;
+        }
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        self.pos += end.chars().count();
+        Ok(if multiline { dedent(&raw) } else { raw })
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('s') => out.push(' '),
+            Some('u') if chars.peek() == Some(&'{') => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                if let Some(c) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    out.push(c);
+                }
+            }
+            // An escaped whitespace is skipped, together with the following
+            // whitespace (KDL v2).
+            Some(c) if c.is_whitespace() => {
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Removes the first and the last line of the multi-line string, and the
+/// indentation of the last line from all the lines.
+fn dedent(s: &str) -> String {
+    let s = s.strip_prefix('\n').unwrap_or(s);
+    let (body, last) = s.rsplit_once('\n').unwrap_or(("", s));
+    if !last.trim().is_empty() {
+        return s.to_string();
+    }
+    body.lines()
+        .map(|line| line.strip_prefix(last).unwrap_or(line.trim_start()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[gtest]
+    fn parses_nodes() {
+        let nodes = parse(
+            r##"
+            // A comment.
+            name "my tool"; bin mytool
+            flag "-v --verbose" help="Print more" global=#true /-ignored=1
+            /* block /* nested */ comment */
+            cmd build r#"raw "string""# (ty)10 \
+                null {
+                arg "<target>" {
+                    choices "linux" "windows\tall"
+                }
+            }
+            /-cmd removed { flag "-x" }
+            about """
+                Multi
+                  line
+                """
+            "##,
+        )
+        .unwrap();
+
+        expect_that!(
+            nodes.iter().map(|n| n.name.clone()).collect::<Vec<_>>(),
+            elements_are![eq("name"), eq("bin"), eq("flag"), eq("cmd"), eq("about")]
+        );
+        expect_that!(nodes[0].arg(0), some(eq("my tool")));
+        expect_that!(nodes[1].arg(0), some(eq("mytool")));
+        expect_that!(nodes[2].prop_str("help"), some(eq("Print more")));
+        expect_that!(nodes[2].prop_bool("global"), eq(true));
+        expect_that!(nodes[2].prop("ignored"), none());
+        expect_that!(
+            nodes[3].args,
+            elements_are![
+                eq(&string("build")),
+                eq(&string("raw \"string\"")),
+                eq(&Value::Number("10".into())),
+                eq(&Value::Null)
+            ]
+        );
+        let choices = &nodes[3].children[0].children[0];
+        expect_that!(
+            choices.args,
+            elements_are![eq(&string("linux")), eq(&string("windows\tall"))]
+        );
+        expect_that!(nodes[4].arg(0), some(eq("Multi\n  line")));
+    }
+
+    #[gtest]
+    fn reports_errors() {
+        expect_that!(
+            parse("a {\n  b \"c\n"),
+            err(eq(&ParseError {
+                line: 3,
+                message: "unterminated string".into()
+            }))
+        );
+        expect_that!(
+            parse("a }").map_err(|e| e.to_string()),
+            err(eq("line 1: unexpected '}'"))
+        );
+    }
+}
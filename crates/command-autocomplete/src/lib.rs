@@ -1,6 +1,67 @@
+//! The implementation of the Command Autocomplete Protocol.
+//!
+//! A CLI can provide the `complete` command with [serve], or with
+//! [parse_or_complete] when it uses clap. The router, the bridges and the
+//! shell integrations require the `cli` feature (enabled by default).
+
+#[cfg(feature = "cli")]
+pub mod argcomplete;
+#[cfg(feature = "cli")]
+pub mod bash_completion;
+#[cfg(feature = "cli")]
+pub mod cache;
+#[cfg(feature = "cli")]
 pub mod carapace;
+#[cfg(feature = "cli")]
+pub mod carapace_spec;
+#[cfg(feature = "cli")]
+pub mod clap_complete;
+#[cfg(feature = "cli")]
+pub mod client;
+#[cfg(feature = "cli")]
+pub mod cobra;
 pub mod complete;
 pub mod connection;
+#[cfg(feature = "cli")]
+pub mod elvish;
+#[cfg(feature = "cli")]
+pub mod filter;
+#[cfg(feature = "cli")]
+pub mod fish;
+pub mod git;
+#[cfg(feature = "cli")]
+pub mod help;
+#[cfg(feature = "cli")]
+pub mod history;
+#[cfg(feature = "cli")]
+pub mod kdl;
+#[cfg(feature = "cli")]
+pub mod man;
+#[cfg(feature = "cli")]
 pub mod nushell;
+pub mod path;
+#[cfg(feature = "cli")]
+pub mod powershell;
+pub mod providers;
+#[cfg(feature = "cli")]
+pub mod query;
+#[cfg(feature = "cli")]
+pub mod repl;
+#[cfg(feature = "cli")]
 pub mod router;
+pub mod server;
+#[cfg(feature = "cli")]
+pub mod shell_words;
+#[cfg(feature = "cli")]
+pub mod spec;
+#[cfg(feature = "cli")]
+pub mod system;
+#[cfg(test)]
+pub mod testing;
 pub mod types;
+#[cfg(feature = "cli")]
+pub mod usage_spec;
+
+pub use complete::{complete_subcommand, parse_or_complete, run_complete};
+pub use server::serve;
+pub use types::{CompleteParams, CompleteResult, CompletionValue, Error};
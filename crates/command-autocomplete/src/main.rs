@@ -1,8 +1,21 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use command_autocomplete::argcomplete::{run_argcomplete, ArgcompleteArgs};
+use command_autocomplete::bash_completion::{run_bash_completion, BashCompletionArgs};
 use command_autocomplete::carapace::{run_carapace, CarapaceArgs};
+use command_autocomplete::clap_complete::{run_clap, ClapArgs};
+use command_autocomplete::client::{run_accepted, AcceptedArgs};
+use command_autocomplete::cobra::{run_cobra, CobraArgs};
 use command_autocomplete::complete::run_complete;
+use command_autocomplete::elvish::{run_elvish, ElvishArgs};
+use command_autocomplete::fish::{run_fish, FishArgs};
+use command_autocomplete::help::{run_help, HelpArgs};
+use command_autocomplete::man::{run_man, ManArgs};
 use command_autocomplete::nushell::{run_nushell, NushellArgs};
+use command_autocomplete::powershell::{run_powershell, PowershellArgs};
+use command_autocomplete::query::{run_query, QueryArgs};
+use command_autocomplete::repl::{run_repl, ReplArgs};
 use command_autocomplete::router::{run_router, RouterArgs};
+use command_autocomplete::spec::{run_spec, SpecArgs};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -13,10 +26,19 @@ struct AppArgs {
 
 #[derive(Debug, Subcommand)]
 enum Command {
+    /// Completes the command line of the shell.
     Shell(ShellArgs),
+    /// Starts the server completing the commands with the configured
+    /// completers.
     Router(RouterArgs),
+    /// Starts a server completing with an existing completion system.
     Bridge(BridgeArgs),
+    /// Starts the server completing this command.
     Complete,
+    /// Prints the completions for the given args, without a shell.
+    Query(QueryArgs),
+    /// Starts a line editor that completes the line through the router.
+    Repl(ReplArgs),
 }
 
 #[derive(Debug, Args)]
@@ -28,9 +50,16 @@ struct ShellArgs {
 #[derive(Debug, Subcommand)]
 enum ShellCommand {
     Nushell(NushellArgs),
+    Powershell(PowershellArgs),
+    Elvish(ElvishArgs),
+    /// Records the value inserted by the shell, to rank it higher in the
+    /// next completions.
+    Accepted(AcceptedArgs),
 }
 
 #[derive(Debug, Args)]
+// The `help` subcommand is taken by the bridge.
+#[command(disable_help_subcommand = true)]
 struct BridgeArgs {
     #[clap(subcommand)]
     command: BridgeCommand,
@@ -39,6 +68,14 @@ struct BridgeArgs {
 #[derive(Debug, Subcommand)]
 enum BridgeCommand {
     Carapace(CarapaceArgs),
+    BashCompletion(BashCompletionArgs),
+    Fish(FishArgs),
+    Cobra(CobraArgs),
+    Clap(ClapArgs),
+    Argcomplete(ArgcompleteArgs),
+    Help(HelpArgs),
+    Man(ManArgs),
+    Spec(SpecArgs),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -47,11 +84,24 @@ fn main() -> anyhow::Result<()> {
     match args.command {
         Command::Bridge(bridge) => match bridge.command {
             BridgeCommand::Carapace(args) => run_carapace(args),
+            BridgeCommand::BashCompletion(args) => run_bash_completion(args),
+            BridgeCommand::Fish(args) => run_fish(args),
+            BridgeCommand::Cobra(args) => run_cobra(args),
+            BridgeCommand::Clap(args) => run_clap(args),
+            BridgeCommand::Argcomplete(args) => run_argcomplete(args),
+            BridgeCommand::Help(args) => run_help(args),
+            BridgeCommand::Man(args) => run_man(args),
+            BridgeCommand::Spec(args) => run_spec(args),
         },
         Command::Shell(shell) => match shell.command {
             ShellCommand::Nushell(args) => run_nushell(args),
+            ShellCommand::Powershell(args) => run_powershell(args),
+            ShellCommand::Elvish(args) => run_elvish(args),
+            ShellCommand::Accepted(args) => run_accepted(args),
         },
         Command::Router(args) => run_router(args),
-        Command::Complete => run_complete(),
+        Command::Complete => run_complete(AppArgs::command()),
+        Command::Query(args) => run_query(args),
+        Command::Repl(args) => run_repl(args),
     }
 }
@@ -0,0 +1,441 @@
+use crate::cache::DiskCache;
+use crate::help::{self, HelpFlag};
+use crate::types::{CompleteParams, CompleteResult, Error, FilesHint};
+use clap::Args;
+use flate2::read::MultiGzDecoder;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Args)]
+pub struct ManArgs {
+    /// Do not cache the parsed man pages on disk.
+    #[arg(long)]
+    no_cache: bool,
+}
+
+pub fn run_man(args: ManArgs) -> anyhow::Result<()> {
+    let completer = ManCompleter {
+        cache: (!args.no_cache).then(|| DiskCache::new("man")),
+    };
+    crate::server::serve(|params| completer.complete(params))
+}
+
+/// The man page directories used when `MANPATH` is not set (or has an empty
+/// component).
+const DEFAULT_MANPATH: &[&str] = &["/usr/local/share/man", "/usr/share/man"];
+
+/// The sections with the commands, in the order they are searched.
+const SECTIONS: &[&str] = &["1", "8", "6"];
+
+struct ManCompleter {
+    cache: Option<DiskCache>,
+}
+
+impl ManCompleter {
+    fn complete(&self, params: CompleteParams) -> Result<CompleteResult, Error> {
+        let Some((current, args)) = params.args.split_last() else {
+            return Err(Error::invalid_request(
+                "params.args is empty, required at least one element",
+            ));
+        };
+        let Some(program) = args
+            .first()
+            .and_then(|p| Path::new(p).file_name())
+            .map(|p| p.to_string_lossy())
+        else {
+            return Ok(CompleteResult::default());
+        };
+
+        let manpath = manpath(&params);
+        // The subcommands have their own pages, e.g. git-commit(1).
+        let subcommand = args[1..].iter().find(|a| !a.starts_with('-'));
+        let page = subcommand
+            .and_then(|sub| find_page(&manpath, &format!("{program}-{sub}")))
+            .or_else(|| find_page(&manpath, &program));
+        let Some(page) = page else {
+            log::info!("man page for {program} not found");
+            return Ok(CompleteResult::default());
+        };
+        let flags = self.flags(&page)?;
+
+        let flag_value = args.last().is_some_and(|arg| {
+            arg.starts_with('-')
+                && !arg.contains('=')
+                && flags
+                    .iter()
+                    .any(|f| f.takes_value && f.names.iter().any(|n| n == arg))
+        });
+        if current.starts_with('-') && !flag_value {
+            return Ok(CompleteResult {
+                values: help::flag_values(&flags, current),
+                ..Default::default()
+            });
+        }
+        // The man pages describe the other args only in prose, so the files
+        // are the best guess.
+        Ok(CompleteResult {
+            files: Some(FilesHint::default()),
+            ..Default::default()
+        })
+    }
+
+    fn flags(&self, page: &Path) -> Result<Vec<HelpFlag>, Error> {
+        let key = crate::cache::file_key(page);
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some(flags) = cache.get(key) {
+                return Ok(flags);
+            }
+        }
+        let flags = parse_man(&read_page(page)?);
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            cache.put(key, &flags);
+        }
+        Ok(flags)
+    }
+}
+
+fn manpath(params: &CompleteParams) -> Vec<PathBuf> {
+    let Some(manpath) = params.env("MANPATH").filter(|m| !m.is_empty()) else {
+        return DEFAULT_MANPATH.iter().map(PathBuf::from).collect();
+    };
+    let mut dirs = vec![];
+    for dir in manpath.split(':') {
+        if dir.is_empty() {
+            dirs.extend(DEFAULT_MANPATH.iter().map(PathBuf::from));
+        } else {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+    dirs
+}
+
+fn find_page(manpath: &[PathBuf], name: &str) -> Option<PathBuf> {
+    manpath.iter().find_map(|dir| {
+        SECTIONS.iter().find_map(|section| {
+            let path = dir.join(format!("man{section}/{name}.{section}"));
+            with_compressed(path)
+        })
+    })
+}
+
+/// Returns the path, or the path of the gzipped file, whichever exists.
+fn with_compressed(path: PathBuf) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path);
+    }
+    let mut gz = path.into_os_string();
+    gz.push(".gz");
+    Some(PathBuf::from(gz)).filter(|p| p.is_file())
+}
+
+/// Reads the page, following the `.so` link of the pages that are aliases of
+/// other pages.
+fn read_page(path: &Path) -> Result<String, Error> {
+    let text = read_file(path)?;
+    if let Some(target) = text.trim().strip_prefix(".so ") {
+        // The target is relative to the root of the man directory.
+        let root = path
+            .parent()
+            .and_then(|p| p.parent())
+            .unwrap_or(Path::new("."));
+        let target = with_compressed(root.join(target.trim())).ok_or_else(|| {
+            Error::internal(format!("{} links to missing {target}", path.display()))
+        })?;
+        return read_file(&target);
+    }
+    Ok(text)
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    let read = |path: &Path| -> std::io::Result<Vec<u8>> {
+        let file = std::fs::File::open(path)?;
+        let mut data = vec![];
+        if path.extension().is_some_and(|e| e == "gz") {
+            MultiGzDecoder::new(file).read_to_end(&mut data)?;
+        } else {
+            std::io::BufReader::new(file).read_to_end(&mut data)?;
+        }
+        Ok(data)
+    };
+    let data = read(path)
+        .map_err(|e| Error::internal(format!("failed to read {}: {e}", path.display())))?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Parses the flags from the tagged paragraphs (`.TP` and `.IP`) of the page,
+/// with the first sentence of the paragraph as the description.
+fn parse_man(page: &str) -> Vec<HelpFlag> {
+    let lines: Vec<_> = page.lines().filter(|l| !is_comment(l)).collect();
+    let mut flags = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let tag = match macro_name(lines[i]) {
+            // The tag is the next line.
+            Some("TP") => {
+                i += 1;
+                lines.get(i).map(|l| line_text(l))
+            }
+            Some("IP") => macro_args(lines[i]).first().map(|a| unescape(a)),
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        i += 1;
+        let mut description = String::new();
+        while i < lines.len() && !is_paragraph(lines[i]) {
+            let text = line_text(lines[i]);
+            if !text.is_empty() {
+                if !description.is_empty() {
+                    description.push(' ');
+                }
+                description.push_str(text.trim());
+            }
+            i += 1;
+        }
+
+        let Some(tag) = tag.filter(|t| t.starts_with('-')) else {
+            continue;
+        };
+        if let Some((names, takes_value)) = help::parse_flag(&tag) {
+            flags.push(HelpFlag {
+                names,
+                takes_value,
+                description: first_sentence(&description),
+            });
+        }
+    }
+    flags
+}
+
+fn first_sentence(text: &str) -> Option<String> {
+    let sentence = match text.find(". ") {
+        Some(end) => &text[..end + 1],
+        None => text,
+    };
+    Some(sentence.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with(".\\\"") || line.starts_with("'\\\"") || line.starts_with("\\\"")
+}
+
+fn macro_name(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix('.').or_else(|| line.strip_prefix('\''))?;
+    rest.split_whitespace().next()
+}
+
+fn is_paragraph(line: &str) -> bool {
+    matches!(
+        macro_name(line),
+        Some("TP" | "IP" | "PP" | "P" | "LP" | "HP" | "SH" | "SS" | "RS" | "RE")
+    )
+}
+
+/// Returns the text of the line, with the font macros (e.g. `.B`) replaced by
+/// their text and the other requests dropped.
+fn line_text(line: &str) -> String {
+    let Some(name) = macro_name(line) else {
+        return unescape(line);
+    };
+    let args = macro_args(line);
+    match name {
+        "B" | "I" | "SM" | "SB" => unescape(&args.join(" ")),
+        // The alternating fonts, e.g. `.BR -V ", " --version`.
+        "BR" | "BI" | "IB" | "IR" | "RB" | "RI" => unescape(&args.concat()),
+        _ => String::new(),
+    }
+}
+
+/// Splits the args of the macro, which can be quoted with `"` (where `""`
+/// is a literal quote).
+fn macro_args(line: &str) -> Vec<String> {
+    let rest = line
+        .trim_start_matches(['.', '\''])
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .map(|(_, rest)| rest)
+        .unwrap_or_default();
+    let mut args = vec![];
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            break;
+        };
+        let mut arg = String::new();
+        if first == '"' {
+            while let Some(c) = chars.next() {
+                if c == '"' && chars.next_if_eq(&'"').is_none() {
+                    break;
+                }
+                arg.push(c);
+            }
+        } else {
+            arg.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+/// Replaces the roff escapes with the text they represent (dropping the font
+/// changes and spacing hints).
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('-') => out.push('-'),
+            Some('e' | '\\') => out.push('\\'),
+            Some(' ' | '~' | '0') => out.push(' '),
+            Some('&' | '^' | '|' | ',' | '/' | ':' | '%' | ')' | 'c') => {}
+            Some('"') => break,
+            Some('f') => {
+                escape_name(&mut chars);
+            }
+            Some('(') => {
+                let name: String = chars.by_ref().take(2).collect();
+                out.push_str(special_char(&name));
+            }
+            Some('[') => {
+                let name: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                out.push_str(special_char(&name));
+            }
+            Some('*' | 'n') => {
+                escape_name(&mut chars);
+            }
+            Some('s') => {
+                chars.next_if(|c| *c == '+' || *c == '-');
+                chars.next_if(|c| c.is_ascii_digit());
+                chars.next_if(|c| c.is_ascii_digit());
+            }
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Skips the name of the escape, e.g. `B`, `(CW` or `[CW]`.
+fn escape_name(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    match chars.next() {
+        Some('(') => {
+            chars.next();
+            chars.next();
+        }
+        Some('[') => for _ in chars.by_ref().take_while(|c| *c != ']') {},
+        _ => {}
+    }
+}
+
+fn special_char(name: &str) -> &'static str {
+    match name {
+        "em" => "—",
+        "en" => "–",
+        "hy" | "mi" => "-",
+        "aq" | "oq" | "cq" => "'",
+        "dq" | "lq" | "rq" => "\"",
+        "bu" => "•",
+        "co" => "©",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::types::{CompletionValue, EnvironmentVariable};
+    use flate2::write::GzEncoder;
+    use googletest::prelude::*;
+    use std::io::Write;
+
+    const PAGE: &str = include_str!("../testdata/man/mytool.1");
+
+    fn flag(names: &[&str], takes_value: bool, description: &str) -> HelpFlag {
+        HelpFlag {
+            names: names.iter().map(|n| n.to_string()).collect(),
+            takes_value,
+            description: Some(description.into()),
+        }
+    }
+
+    #[gtest]
+    fn parses_flags() {
+        expect_that!(
+            parse_man(PAGE),
+            elements_are![
+                eq(&flag(&["-a", "--all"], false, "build all the targets")),
+                eq(&flag(
+                    &["--block-size"],
+                    true,
+                    "with -l, scale sizes by SIZE."
+                )),
+                eq(&flag(&["--color"], false, "color the output WHEN")),
+                eq(&flag(
+                    &["--help"],
+                    false,
+                    "Output a usage message and exit."
+                )),
+                eq(&flag(
+                    &["-V", "--version"],
+                    false,
+                    "Output the version number of mytool and exit."
+                )),
+                eq(&flag(
+                    &["-e", "--regexp"],
+                    true,
+                    "Use PATTERNS as the patterns."
+                )),
+                eq(&flag(&["-j", "--jobs"], true, "Run N jobs in parallel.")),
+            ]
+        );
+    }
+
+    #[gtest]
+    fn completes_flags_from_manpath() {
+        let dir = TempDir::new("man");
+        std::fs::create_dir_all(dir.join("man1")).unwrap();
+        let mut encoder = GzEncoder::new(
+            std::fs::File::create(dir.join("man1/mytool.1.gz")).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(PAGE.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        std::fs::write(dir.join("man1/mt.1"), ".so man1/mytool.1\n").unwrap();
+
+        let completer = ManCompleter { cache: None };
+        let params = |args: &[&str]| CompleteParams {
+            args: args.iter().map(|a| a.to_string()).collect(),
+            working_dir: None,
+            envs: vec![EnvironmentVariable {
+                name: "MANPATH".into(),
+                value: dir.to_string_lossy().into_owned(),
+            }],
+            ..Default::default()
+        };
+        let result = completer.complete(params(&["/bin/mt", "--c"])).unwrap();
+        expect_that!(
+            result.values,
+            elements_are![matches_pattern!(CompletionValue {
+                value: eq("--color"),
+                description: some(eq("color the output WHEN")),
+            })]
+        );
+
+        let result = completer.complete(params(&["mytool", "-j", ""])).unwrap();
+        expect_that!(result.values, empty());
+        expect_that!(result.files, some(eq(&FilesHint::default())));
+
+        let result = completer.complete(params(&["unknown", "-"])).unwrap();
+        expect_that!(result.values, empty());
+    }
+}
@@ -1,89 +1,93 @@
-use crate::connection::{ConnectionSender, Transport};
-use crate::types::{CompleteParams, CompleteResult, Error};
-use anyhow::Context;
+use crate::client::{CommandArgs, ServerArgs};
+use crate::shell_words::{self, Dialect, Quote};
+use crate::types::CompletionValue;
 use clap::Args;
 use serde_json::json;
-use std::process::{Command, Stdio};
 
 #[derive(Debug, Args)]
 pub struct NushellArgs {
-    /// args of the command that is being completed
-    #[arg(last = true)]
-    command: Vec<String>,
+    #[command(flatten)]
+    server: ServerArgs,
+    #[command(flatten)]
+    command: CommandArgs,
 }
 
 pub fn run_nushell(args: NushellArgs) -> anyhow::Result<()> {
-    // TODO: make it customizable (we should actually invoke a router)
-    let mut child = Command::new("command-autocomplete")
-        .args(["router"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
-
-    let stdin = child.stdin.take().context("missing stdin")?;
-    let stdout = child.stdout.take().context("missing stdout")?;
-
-    let (transport, join_handle) = Transport::raw(stdout, stdin);
-    let (sender, receiver) = crate::connection::new_connection(transport);
+    let (line, params) = args.command.into_params(Dialect::Nushell);
+    let values = crate::client::complete_in_shell(&args.server, params);
+    println!("{}", format_values(values, line.quote));
+    Ok(())
+}
 
-    let recv_join_handle = std::thread::spawn(move || {
-        // This is required to read the incoming responses.
-        while let Some(req) = receiver.next_request() {
-            let r = req.reply_err(Error::invalid_request("no requests expected"));
-            if r.is_err() {
-                log::warn!("The connection closed unexpectedly, stopping the receving loop");
-                break;
+fn format_values(values: Vec<CompletionValue>, quote: Option<Quote>) -> serde_json::Value {
+    json!(values
+        .into_iter()
+        .map(|v| {
+            let mut record = json! ({
+                "value": shell_words::quote(&v.value, Dialect::Nushell, quote),
+                "description": v.description,
+            });
+            if let Some(style) = v.style.as_deref().and_then(format_style) {
+                record["style"] = style;
             }
-        }
-    });
-    if let Err(err) = complete_and_shutdown(args, sender) {
-        log::error!("Completion failed, will kill subprocess: {}", err);
-        if let Err(err) = child.kill() {
-            log::warn!("Failed to kill subprocess: {err}")
+            record
+        })
+        .collect::<Vec<_>>())
+}
+
+/// Converts the style of the value into nushell style record.
+fn format_style(style: &str) -> Option<serde_json::Value> {
+    let mut fg = None;
+    let mut bg = None;
+    let mut attr = String::new();
+    for part in style.split_whitespace() {
+        match part {
+            "bold" => attr.push('b'),
+            "dim" => attr.push('d'),
+            "italic" => attr.push('i'),
+            "underlined" => attr.push('u'),
+            "blink" => attr.push('l'),
+            "inverse" => attr.push('r'),
+            "hidden" => attr.push('h'),
+            "strikethrough" => attr.push('s'),
+            _ => match part.strip_prefix("bg-") {
+                Some(color) => bg = Some(format_color(color)),
+                None => fg = Some(format_color(part.strip_prefix("fg-").unwrap_or(part))),
+            },
         }
     }
-
-    if let Err(err) = child.wait() {
-        log::warn!("Failed to wait for the subprocess: {err}");
+    let mut record = serde_json::Map::new();
+    if let Some(fg) = fg {
+        record.insert("fg".into(), fg.into());
     }
-    if let Err(err) = recv_join_handle.join() {
-        log::warn!("receiving thread failed: {:?}", err);
+    if let Some(bg) = bg {
+        record.insert("bg".into(), bg.into());
     }
-    if let Err(err) = join_handle.join() {
-        log::warn!("connection threads failed: {:?}", err);
+    if !attr.is_empty() {
+        record.insert("attr".into(), attr.into());
     }
-    Ok(())
+    (!record.is_empty()).then_some(record.into())
 }
 
-fn complete_and_shutdown(args: NushellArgs, sender: ConnectionSender) -> anyhow::Result<()> {
-    // TODO: handle unwrap
-    let res_handle = sender
-        .send(
-            "complete",
-            CompleteParams {
-                args: args.command.clone(),
-            },
-        )
-        .context("complete command failed")?;
-
-    // TODO: handle unwrap
-    let result: CompleteResult = res_handle.wait().context("complete command failed")?;
+fn format_color(color: &str) -> String {
+    match color.strip_prefix("bright-") {
+        Some(color) => format!("light_{color}"),
+        None => color.to_string(),
+    }
+}
 
-    // TODO: handle unwrap
-    sender.shutdown().unwrap().wait().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
 
-    println!(
-        "{}",
-        json!(result
-            .values
-            .into_iter()
-            .map(|v| {
-                json! ({
-                    "value": v.value,
-                    "description": v.description,
-                })
-            })
-            .collect::<Vec<_>>())
-    );
-    Ok(())
+    #[gtest]
+    fn formats_style() {
+        expect_that!(format_style("blue"), some(eq(&json!({"fg": "blue"}))));
+        expect_that!(
+            format_style("bold underlined bright-red bg-black"),
+            some(eq(&json!({"fg": "light_red", "bg": "black", "attr": "bu"})))
+        );
+        expect_that!(format_style(""), none());
+    }
 }
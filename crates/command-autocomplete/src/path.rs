@@ -0,0 +1,226 @@
+//! Completing the filesystem paths, for the completers returning the
+//! [FilesHint] and the commands without a completer.
+
+use crate::types::{CompleteParams, CompleteResult, CompletionValue, FilesHint, ValueKind};
+use std::path::{Path, PathBuf};
+
+/// Replaces the files hint of the result with the paths matching it, so that
+/// the clients don't need to complete the paths on their own. The value of a
+/// flag given in the same arg (`--flag=<prefix>`) is completed as a path, and
+/// prefixed with the flag.
+pub fn expand_files_hint(result: &mut CompleteResult, params: &CompleteParams) {
+    let Some(hint) = result.files.take() else {
+        return;
+    };
+    let arg = params.args.last().map_or("", String::as_str);
+    let (flag, prefix) = match arg.split_once('=') {
+        Some((name, prefix)) if name.starts_with('-') => (Some(name), prefix),
+        _ => (None, arg),
+    };
+    let mut paths = complete_paths(prefix, &hint, params);
+    if let Some(name) = flag {
+        for v in &mut paths {
+            v.value = format!("{name}={}", v.value);
+        }
+    }
+    result.values.extend(paths);
+}
+
+/// Lists the paths starting with the prefix, relative to the working directory
+/// of the request (or the root of the hint). The hidden files are listed only
+/// when the prefix of the name starts with a dot, `~` is the home directory.
+/// The directories end with a slash (with `no_space` set), so that the
+/// completion can continue inside of them.
+pub fn complete_paths(
+    prefix: &str,
+    hint: &FilesHint,
+    params: &CompleteParams,
+) -> Vec<CompletionValue> {
+    let (dir, name_prefix) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..i + 1], &prefix[i + 1..]),
+        None if prefix == "~" => return home_value(params),
+        None => ("", prefix),
+    };
+    let Some(listed) = resolve_dir(dir, hint, params) else {
+        return vec![];
+    };
+    let entries = match std::fs::read_dir(&listed) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("failed to list {}: {err}", listed.display());
+            return vec![];
+        }
+    };
+    let mut values: Vec<CompletionValue> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(name_prefix)
+                || name.starts_with('.') && !name_prefix.starts_with('.')
+            {
+                return None;
+            }
+            // The metadata follows the symlinks, the broken ones are files.
+            let is_dir = std::fs::metadata(entry.path()).is_ok_and(|m| m.is_dir());
+            if is_dir {
+                return Some(CompletionValue {
+                    value: format!("{dir}{name}/"),
+                    display: Some(format!("{name}/")),
+                    no_space: true,
+                    kind: Some(ValueKind::Directory),
+                    ..Default::default()
+                });
+            }
+            if hint.directories_only || !matches_extensions(&name, &hint.extensions) {
+                return None;
+            }
+            Some(CompletionValue {
+                value: format!("{dir}{name}"),
+                display: Some(name),
+                kind: Some(ValueKind::File),
+                ..Default::default()
+            })
+        })
+        .collect();
+    values.sort_by(|a, b| a.value.cmp(&b.value));
+    values
+}
+
+/// Resolves the directory part of the prefix (e.g. `~/src/`) to the listed
+/// directory.
+fn resolve_dir(dir: &str, hint: &FilesHint, params: &CompleteParams) -> Option<PathBuf> {
+    if let Some(rest) = dir.strip_prefix("~/") {
+        return Some(PathBuf::from(home(params)?).join(rest));
+    }
+    let mut base = match &params.working_dir {
+        Some(working_dir) => working_dir.clone(),
+        None => std::env::current_dir().ok()?,
+    };
+    if let Some(root) = &hint.root {
+        base = base.join(root);
+    }
+    // An absolute dir replaces the base when joined.
+    Some(base.join(if dir.is_empty() { "." } else { dir }))
+}
+
+fn home_value(params: &CompleteParams) -> Vec<CompletionValue> {
+    if home(params).is_none() {
+        return vec![];
+    }
+    vec![CompletionValue {
+        value: "~/".to_string(),
+        no_space: true,
+        kind: Some(ValueKind::Directory),
+        ..Default::default()
+    }]
+}
+
+/// The home directory of the completed command.
+fn home(params: &CompleteParams) -> Option<String> {
+    params.env("HOME").filter(|home| !home.is_empty())
+}
+
+fn matches_extensions(name: &str, extensions: &[String]) -> bool {
+    extensions.is_empty()
+        || Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| extensions.iter().any(|x| x == e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{values, TempDir};
+    use crate::types::EnvironmentVariable;
+    use googletest::prelude::*;
+
+    #[cfg(unix)]
+    #[gtest]
+    fn completes_paths() {
+        let dir = TempDir::new("path");
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        for file in ["Cargo.toml", "README.md", ".gitignore", "src/main.rs"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        std::os::unix::fs::symlink(dir.join("src"), dir.join("source")).unwrap();
+        let params = CompleteParams {
+            args: vec!["cat".into(), "".into()],
+            working_dir: Some(dir.to_path_buf()),
+            envs: vec![EnvironmentVariable {
+                name: "HOME".into(),
+                value: dir.join("src").display().to_string(),
+            }],
+            ..Default::default()
+        };
+        let hint = FilesHint::default();
+
+        let result = complete_paths("", &hint, &params);
+        expect_that!(
+            values(&result),
+            elements_are![eq("Cargo.toml"), eq("README.md"), eq("source/"), eq("src/")]
+        );
+        expect_that!(result[0].kind, some(eq(ValueKind::File)));
+        expect_that!(result[0].no_space, eq(false));
+        expect_that!(result[3].kind, some(eq(ValueKind::Directory)));
+        expect_that!(result[3].no_space, eq(true));
+        expect_that!(
+            values(&complete_paths(".", &hint, &params)),
+            elements_are![eq(".git/"), eq(".gitignore")]
+        );
+        expect_that!(
+            values(&complete_paths("src/", &hint, &params)),
+            elements_are![eq("src/main.rs"), eq("src/nested/")]
+        );
+        expect_that!(
+            values(&complete_paths("~/m", &hint, &params)),
+            elements_are![eq("~/main.rs")]
+        );
+        expect_that!(
+            values(&complete_paths("~", &hint, &params)),
+            elements_are![eq("~/")]
+        );
+        let toml = FilesHint {
+            extensions: vec!["toml".into()],
+            ..Default::default()
+        };
+        expect_that!(
+            values(&complete_paths("", &toml, &params)),
+            elements_are![eq("Cargo.toml"), eq("source/"), eq("src/")]
+        );
+        let dirs = FilesHint {
+            directories_only: true,
+            root: Some("src".into()),
+            ..Default::default()
+        };
+        expect_that!(
+            values(&complete_paths("", &dirs, &params)),
+            elements_are![eq("nested/")]
+        );
+        let absolute = format!("{}/R", dir.display());
+        expect_that!(
+            values(&complete_paths(&absolute, &hint, &params)),
+            elements_are![eq(&format!("{}/README.md", dir.display()))]
+        );
+    }
+
+    #[gtest]
+    fn expands_inline_flag_value() {
+        let dir = TempDir::new("path-flag");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "").unwrap();
+        let mut result = CompleteResult {
+            files: Some(FilesHint::default()),
+            ..Default::default()
+        };
+        let params = CompleteParams {
+            args: vec!["tool".into(), "--output=sr".into()],
+            working_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        expand_files_hint(&mut result, &params);
+        expect_that!(values(&result.values), elements_are![eq("--output=src/")]);
+        expect_that!(result.values[0].display, some(eq("src/")));
+    }
+}
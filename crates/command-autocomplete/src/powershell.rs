@@ -0,0 +1,81 @@
+use crate::client::{CommandArgs, ServerArgs};
+use crate::shell_words::{self, Dialect, Quote};
+use crate::types::CompletionValue;
+use clap::Args;
+use serde::Serialize;
+
+#[derive(Debug, Args)]
+pub struct PowershellArgs {
+    #[command(flatten)]
+    server: ServerArgs,
+    #[command(flatten)]
+    command: CommandArgs,
+}
+
+pub fn run_powershell(args: PowershellArgs) -> anyhow::Result<()> {
+    let (line, params) = args.command.into_params(Dialect::Powershell);
+    let values = crate::client::complete_in_shell(&args.server, params);
+    println!("{}", format_values(values, line.quote));
+    Ok(())
+}
+
+/// The arguments of `System.Management.Automation.CompletionResult` constructor.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CompletionResult {
+    completion_text: String,
+    list_item_text: String,
+    result_type: &'static str,
+    tool_tip: String,
+}
+
+/// Formats the values as a json array, that can be converted into
+/// `CompletionResult`s with `ConvertFrom-Json`.
+fn format_values(values: Vec<CompletionValue>, quote: Option<Quote>) -> String {
+    let results: Vec<_> = values
+        .into_iter()
+        .map(|v| CompletionResult {
+            list_item_text: v.display.clone().unwrap_or_else(|| v.value.clone()),
+            // PowerShell rejects an empty tool tip, so we fall back to the value.
+            tool_tip: v.description.unwrap_or_else(|| v.value.clone()),
+            completion_text: shell_words::quote(&v.value, Dialect::Powershell, quote),
+            result_type: "ParameterValue",
+        })
+        .collect();
+    serde_json::to_string(&results).expect("serialization of completion results can't fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn formats_values() {
+        let values = vec![
+            CompletionValue {
+                value: "--verbose".into(),
+                description: Some("Use verbose output".into()),
+                ..Default::default()
+            },
+            CompletionValue {
+                value: "build".into(),
+                description: None,
+                ..Default::default()
+            },
+            CompletionValue {
+                value: "with space \"quoted\"".into(),
+                description: Some("Special $chars".into()),
+                ..Default::default()
+            },
+            CompletionValue {
+                value: "src/".into(),
+                display: Some("src".into()),
+                no_space: true,
+                ..Default::default()
+            },
+        ];
+        let golden = include_str!("../testdata/powershell.golden");
+        expect_that!(format_values(values, None) + "\n", eq(golden));
+    }
+}
@@ -0,0 +1,381 @@
+//! The common sources of the values (environment variables, users, processes,
+//! ...), for the spec server and the custom servers. The values are not
+//! filtered by the prefix of the completed arg.
+
+use crate::types::{CompleteParams, CompletionValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    /// The names of the environment variables of the completed command.
+    Env,
+    /// The users from `/etc/passwd`.
+    Users,
+    /// The groups from `/etc/group`.
+    Groups,
+    /// The ids of the running processes.
+    Pids,
+    /// The names of the signals (without the `SIG` prefix).
+    Signals,
+    /// The network interfaces.
+    Interfaces,
+    /// The hosts from `/etc/hosts` and `~/.ssh/config`.
+    Hosts,
+    /// The local and remote branches of the git repository.
+    GitBranches,
+    GitTags,
+    GitRemotes,
+    GitStashes,
+    /// The changed and untracked files of the git repository.
+    GitChanges,
+}
+
+impl Provider {
+    pub fn values(self, params: &CompleteParams) -> Vec<CompletionValue> {
+        match self {
+            Provider::Env => env_vars(params),
+            Provider::Users => users(),
+            Provider::Groups => groups(),
+            Provider::Pids => pids(),
+            Provider::Signals => signals(),
+            Provider::Interfaces => interfaces(),
+            Provider::Hosts => hosts(params),
+            Provider::GitBranches => crate::git::branches(params),
+            Provider::GitTags => crate::git::tags(params),
+            Provider::GitRemotes => crate::git::remotes(params),
+            Provider::GitStashes => crate::git::stashes(params),
+            Provider::GitChanges => crate::git::changes(params),
+        }
+    }
+}
+
+/// The names of the environment variables. The values are not shown, as they
+/// can be secrets.
+pub fn env_vars(params: &CompleteParams) -> Vec<CompletionValue> {
+    let mut names: Vec<String> = if params.envs.is_empty() {
+        std::env::vars_os()
+            .map(|(name, _)| name.to_string_lossy().into_owned())
+            .collect()
+    } else {
+        params.envs.iter().map(|e| e.name.clone()).collect()
+    };
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| CompletionValue {
+            value: name,
+            ..Default::default()
+        })
+        .collect()
+}
+
+pub fn users() -> Vec<CompletionValue> {
+    parse_passwd(&read("/etc/passwd"))
+}
+
+/// Parses the `name:password:uid:gid:gecos:home:shell` lines, the users are
+/// described by their full names (or ids).
+pub fn parse_passwd(content: &str) -> Vec<CompletionValue> {
+    records(content)
+        .filter_map(|fields| {
+            let uid = fields.get(2)?;
+            let full_name = fields.get(4).and_then(|gecos| gecos.split(',').next());
+            Some(CompletionValue {
+                value: fields[0].to_string(),
+                description: Some(match full_name {
+                    Some(full_name) if !full_name.is_empty() => format!("{full_name} ({uid})"),
+                    _ => format!("uid {uid}"),
+                }),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+pub fn groups() -> Vec<CompletionValue> {
+    parse_group(&read("/etc/group"))
+}
+
+/// Parses the `name:password:gid:members` lines, the groups are described by
+/// their ids and members.
+pub fn parse_group(content: &str) -> Vec<CompletionValue> {
+    records(content)
+        .filter_map(|fields| {
+            let gid = fields.get(2)?;
+            let members = fields.get(3).copied().unwrap_or_default();
+            Some(CompletionValue {
+                value: fields[0].to_string(),
+                description: Some(match members {
+                    "" => format!("gid {gid}"),
+                    members => format!("gid {gid}: {}", members.replace(',', ", ")),
+                }),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// The non-empty and non-comment lines split by colons.
+fn records(content: &str) -> impl Iterator<Item = Vec<&str>> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| line.split(':').collect())
+}
+
+/// The ids of the processes (from `/proc`), described by their command lines.
+pub fn pids() -> Vec<CompletionValue> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return vec![];
+    };
+    let mut pids: Vec<u32> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort();
+    pids.into_iter()
+        .map(|pid| {
+            let dir = Path::new("/proc").join(pid.to_string());
+            let cmdline = read(dir.join("cmdline")).replace('\0', " ");
+            let command = match cmdline.trim() {
+                // The kernel threads have no command line.
+                "" => format!("[{}]", read(dir.join("comm")).trim()),
+                cmdline => cmdline.to_string(),
+            };
+            CompletionValue {
+                value: pid.to_string(),
+                description: Some(truncate(&command, 60)),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+const SIGNALS: &[(&str, &str)] = &[
+    ("HUP", "hangup"),
+    ("INT", "interrupt"),
+    ("QUIT", "quit"),
+    ("ILL", "illegal instruction"),
+    ("TRAP", "trace trap"),
+    ("ABRT", "abort"),
+    ("BUS", "bus error"),
+    ("FPE", "floating point exception"),
+    ("KILL", "kill (cannot be caught)"),
+    ("USR1", "user defined signal 1"),
+    ("SEGV", "segmentation fault"),
+    ("USR2", "user defined signal 2"),
+    ("PIPE", "broken pipe"),
+    ("ALRM", "alarm clock"),
+    ("TERM", "termination"),
+    ("STKFLT", "stack fault"),
+    ("CHLD", "child stopped or exited"),
+    ("CONT", "continue"),
+    ("STOP", "stop (cannot be caught)"),
+    ("TSTP", "stop from the terminal"),
+    ("TTIN", "background read from the terminal"),
+    ("TTOU", "background write to the terminal"),
+    ("URG", "urgent data on a socket"),
+    ("XCPU", "CPU time limit exceeded"),
+    ("XFSZ", "file size limit exceeded"),
+    ("VTALRM", "virtual alarm clock"),
+    ("PROF", "profiling timer expired"),
+    ("WINCH", "window size change"),
+    ("IO", "I/O possible"),
+    ("PWR", "power failure"),
+    ("SYS", "bad system call"),
+];
+
+/// The names of the signals, described by their numbers (on Linux) and
+/// meanings.
+pub fn signals() -> Vec<CompletionValue> {
+    SIGNALS
+        .iter()
+        .enumerate()
+        .map(|(i, (name, description))| CompletionValue {
+            value: name.to_string(),
+            description: Some(format!("{}: {description}", i + 1)),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// The network interfaces (from `/sys/class/net`), described by their state.
+pub fn interfaces() -> Vec<CompletionValue> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/net") else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| {
+            let state = read(Path::new("/sys/class/net").join(&name).join("operstate"));
+            CompletionValue {
+                value: name,
+                description: Some(state.trim().to_string()).filter(|s| !s.is_empty()),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// The hosts from `/etc/hosts` and `~/.ssh/config` of the completed command.
+pub fn hosts(params: &CompleteParams) -> Vec<CompletionValue> {
+    let mut values = parse_hosts(&read("/etc/hosts"));
+    if let Some(home) = params.env("HOME") {
+        values.extend(parse_ssh_config(&read(
+            Path::new(&home).join(".ssh/config"),
+        )));
+    }
+    let mut seen = HashSet::new();
+    values.retain(|v| seen.insert(v.value.clone()));
+    values
+}
+
+/// Parses the `address name [aliases...]` lines, the names are described by
+/// the addresses.
+pub fn parse_hosts(content: &str) -> Vec<CompletionValue> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| {
+            let mut fields = line.split_whitespace();
+            let address = fields.next().unwrap_or_default().to_string();
+            fields.map(move |name| CompletionValue {
+                value: name.to_string(),
+                description: Some(address.clone()),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Parses the `Host` entries (without the patterns), described by their
+/// `HostName`.
+pub fn parse_ssh_config(content: &str) -> Vec<CompletionValue> {
+    let mut values: Vec<CompletionValue> = vec![];
+    // The values of the current `Host` entry.
+    let mut current = 0..0;
+    for line in content.lines() {
+        let line = line.trim();
+        let (keyword, args) = line
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or((line, ""));
+        let args = args.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+        if keyword.eq_ignore_ascii_case("host") {
+            let start = values.len();
+            values.extend(
+                args.split_whitespace()
+                    .filter(|host| !host.contains(['*', '?', '!']))
+                    .map(|host| CompletionValue {
+                        value: host.to_string(),
+                        ..Default::default()
+                    }),
+            );
+            current = start..values.len();
+        } else if keyword.eq_ignore_ascii_case("hostname") {
+            for value in &mut values[current.clone()] {
+                value.description = Some(args.to_string());
+            }
+        } else if keyword.eq_ignore_ascii_case("match") {
+            current = 0..0;
+        }
+    }
+    values
+}
+
+/// Reads the file, the missing files are empty.
+fn read(path: impl AsRef<Path>) -> String {
+    std::fs::read(path)
+        .map(|data| String::from_utf8_lossy(&data).into_owned())
+        .unwrap_or_default()
+}
+
+fn truncate(s: &str, max_chars: usize) -> String {
+    match s.char_indices().nth(max_chars) {
+        Some((i, _)) => format!("{}…", &s[..i]),
+        None => s.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{pair, pairs};
+    use crate::types::EnvironmentVariable;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn parses_system_files() {
+        expect_that!(
+            pairs(parse_passwd(
+                "root:x:0:0:root:/root:/bin/bash\n\
+                 alice:x:1000:1000:Alice Smith,,,:/home/alice:/bin/zsh\n\
+                 nobody:x:65534:65534::/nonexistent:/usr/sbin/nologin\n"
+            )),
+            elements_are![
+                eq(&pair("root", "root (0)")),
+                eq(&pair("alice", "Alice Smith (1000)")),
+                eq(&pair("nobody", "uid 65534"))
+            ]
+        );
+        expect_that!(
+            pairs(parse_group("wheel:x:10:alice,bob\nusers:x:100:\n")),
+            elements_are![
+                eq(&pair("wheel", "gid 10: alice, bob")),
+                eq(&pair("users", "gid 100"))
+            ]
+        );
+        expect_that!(
+            pairs(parse_hosts(
+                "# comment\n127.0.0.1 localhost\n10.0.0.2\tbuild build.lan # the builder\n"
+            )),
+            elements_are![
+                eq(&pair("localhost", "127.0.0.1")),
+                eq(&pair("build", "10.0.0.2")),
+                eq(&pair("build.lan", "10.0.0.2"))
+            ]
+        );
+        expect_that!(
+            pairs(parse_ssh_config(
+                "Host *\n  User me\nHost web web-prod !bastion\n  HostName=web.example.com\n\
+                 Match host x\n  HostName ignored\nhost db\n"
+            )),
+            elements_are![
+                eq(&pair("web", "web.example.com")),
+                eq(&pair("web-prod", "web.example.com")),
+                eq(&pair("db", ""))
+            ]
+        );
+    }
+
+    #[gtest]
+    fn lists_env_vars_and_signals() {
+        let params = CompleteParams {
+            args: vec![],
+            working_dir: None,
+            envs: vec![
+                EnvironmentVariable {
+                    name: "PATH".into(),
+                    value: "/bin".into(),
+                },
+                EnvironmentVariable {
+                    name: "API_TOKEN".into(),
+                    value: "secret".into(),
+                },
+            ],
+            ..Default::default()
+        };
+        expect_that!(
+            pairs(Provider::Env.values(&params)),
+            elements_are![eq(&pair("API_TOKEN", "")), eq(&pair("PATH", ""))]
+        );
+        let signals = pairs(Provider::Signals.values(&params));
+        expect_that!(signals[8], eq(&pair("KILL", "9: kill (cannot be caught)")));
+        expect_that!(signals[14], eq(&pair("TERM", "15: termination")));
+    }
+}
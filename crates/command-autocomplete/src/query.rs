@@ -0,0 +1,211 @@
+use crate::client::{Client, ServerArgs};
+use crate::shell_words::{self, Dialect};
+use crate::types::CompleteResult;
+use anyhow::Context;
+use clap::Args;
+use serde_json::json;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Args)]
+pub struct QueryArgs {
+    /// The format of the printed result.
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// The working directory of the completed command (defaults to the current
+    /// directory).
+    #[arg(long)]
+    cwd: Option<PathBuf>,
+    /// The server to query instead of the router (e.g. `--server "jj complete"`).
+    #[arg(long, conflicts_with_all = ["router_bin", "router_config", "socket"])]
+    server: Option<String>,
+    /// Don't use the cached results of the completers.
+    #[arg(long)]
+    refresh: bool,
+    /// The maximal number of the returned values.
+    #[arg(long)]
+    max_results: Option<usize>,
+    #[command(flatten)]
+    router: ServerArgs,
+    /// args of the command that is being completed
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+pub fn run_query(args: QueryArgs) -> anyhow::Result<()> {
+    let mut params = crate::client::complete_params(args.command);
+    params.refresh = args.refresh;
+    params.max_results = args.max_results;
+    if let Some(cwd) = args.cwd {
+        params.working_dir = Some(cwd);
+    }
+
+    let start = Instant::now();
+    let client = match &args.server {
+        Some(server) => {
+            let words = shell_words::split(server, Dialect::Bash);
+            let (program, words) = words.split_first().context("--server can't be empty")?;
+            let mut command = std::process::Command::new(program);
+            command.args(words);
+            Client::spawn(command)?
+        }
+        None => Client::connect(&args.router)?,
+    };
+    let result = client.complete(params);
+    let shutdown = client.shutdown();
+    let elapsed = start.elapsed();
+    let result = result?;
+    shutdown?;
+
+    match args.format {
+        Format::Table => print!("{}", format_table(&result, elapsed)),
+        Format::Json => println!("{}", format_json(&result, elapsed)),
+    }
+    Ok(())
+}
+
+fn format_json(result: &CompleteResult, elapsed: Duration) -> serde_json::Value {
+    json!({
+        "completer": result.completer,
+        "usage": result.usage,
+        "diagnostics": result.diagnostics,
+        "keep_order": result.keep_order,
+        "files": result.files,
+        "is_incomplete": result.is_incomplete,
+        "elapsed_ms": elapsed.as_millis(),
+        "values": result.values,
+    })
+}
+
+fn format_table(result: &CompleteResult, elapsed: Duration) -> String {
+    let width = result
+        .values
+        .iter()
+        .map(|v| v.value.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    for v in &result.values {
+        match &v.description {
+            Some(description) => {
+                out.push_str(&format!("{:width$}  {}\n", v.value, description));
+            }
+            None => {
+                out.push_str(&v.value);
+                out.push('\n');
+            }
+        }
+    }
+    for diagnostic in &result.diagnostics {
+        out.push_str(&format!(
+            "-- {:?}: {}\n",
+            diagnostic.severity, diagnostic.message
+        ));
+    }
+    if let Some(files) = &result.files {
+        out.push_str(&format!("-- and files matching {}\n", json!(files)));
+    }
+    if let Some(usage) = &result.usage {
+        out.push_str(&format!("-- usage: {usage}\n"));
+    }
+    if result.is_incomplete {
+        out.push_str("-- more values are available\n");
+    }
+    out.push_str(&format!(
+        "-- {} values from {} in {}ms\n",
+        result.values.len(),
+        result.completer.as_deref().unwrap_or("unknown completer"),
+        elapsed.as_millis()
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CompletionValue, Diagnostic, Severity};
+    use googletest::prelude::*;
+
+    fn result() -> CompleteResult {
+        CompleteResult {
+            values: vec![
+                CompletionValue {
+                    value: "build".into(),
+                    description: Some("Build the project".into()),
+                    ..Default::default()
+                },
+                CompletionValue {
+                    value: "b".into(),
+                    ..Default::default()
+                },
+            ],
+            diagnostics: vec![Diagnostic {
+                severity: Severity::Warning,
+                message: "slow completer".into(),
+            }],
+            completer: Some("mytool".into()),
+            is_incomplete: true,
+            ..Default::default()
+        }
+    }
+
+    #[derive(Debug, clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        query: QueryArgs,
+    }
+
+    #[gtest]
+    fn parses_args() {
+        use clap::Parser;
+        let args = Cli::try_parse_from(["query", "--format", "json", "--", "jj", ""]).unwrap();
+        expect_that!(args.query.format, eq(Format::Json));
+        expect_that!(args.query.command, elements_are![eq("jj"), eq("")]);
+        let args = [
+            "query",
+            "--server",
+            "jj complete",
+            "--socket",
+            "s",
+            "--",
+            "jj",
+        ];
+        expect_that!(Cli::try_parse_from(args), err(anything()));
+    }
+
+    #[gtest]
+    fn formats_table() {
+        expect_that!(
+            format_table(&result(), Duration::from_millis(12)),
+            eq("build  Build the project\n\
+                b\n\
+                -- Warning: slow completer\n\
+                -- more values are available\n\
+                -- 2 values from mytool in 12ms\n")
+        );
+        expect_that!(
+            format_table(&CompleteResult::default(), Duration::ZERO),
+            eq("-- 0 values from unknown completer in 0ms\n")
+        );
+    }
+
+    #[gtest]
+    fn formats_json() {
+        let json = format_json(&result(), Duration::from_millis(12));
+        expect_that!(json["completer"], eq(&json!("mytool")));
+        expect_that!(json["is_incomplete"], eq(&json!(true)));
+        expect_that!(json["elapsed_ms"], eq(&json!(12)));
+        expect_that!(
+            json["diagnostics"],
+            eq(&json!([{"severity": "warning", "message": "slow completer"}]))
+        );
+        expect_that!(json["values"][0]["value"], eq(&json!("build")));
+        expect_that!(json["values"][1]["value"], eq(&json!("b")));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::client::ServerArgs;
+use crate::shell_words::{self, Dialect, Quote};
+use crate::types::{AcceptedParams, CompletionValue};
+use clap::Args;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::line_buffer::LineBuffer;
+use rustyline::validate::Validator;
+use rustyline::{Changeset, CompletionType, Config, Editor, Helper};
+use std::cell::RefCell;
+
+#[derive(Debug, Args)]
+pub struct ReplArgs {
+    #[command(flatten)]
+    server: ServerArgs,
+    /// The shell whose rules are used to split the line and quote the values.
+    #[arg(long, value_enum, default_value = "bash")]
+    dialect: Dialect,
+}
+
+pub fn run_repl(args: ReplArgs) -> anyhow::Result<()> {
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .auto_add_history(true)
+        .build();
+    let mut editor = Editor::with_config(config)?;
+    editor.set_helper(Some(ReplHelper {
+        server: args.server,
+        dialect: args.dialect,
+        last: RefCell::new(None),
+    }));
+    println!("Press tab to complete, enter to print the args of the line, ctrl-d to exit.");
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                let args = shell_words::parse(&line, line.len(), args.dialect).args;
+                println!("{}", serde_json::to_string(&args)?);
+            }
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+struct ReplHelper {
+    server: ServerArgs,
+    dialect: Dialect,
+    /// The last completion, to notify the router which of its values was
+    /// inserted.
+    last: RefCell<Option<LastCompletion>>,
+}
+
+struct LastCompletion {
+    args: Vec<String>,
+    /// The values, with their replacements in the line.
+    values: Vec<(String, String)>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let parsed = shell_words::parse(line, pos, self.dialect);
+        let params = crate::client::complete_params(parsed.args.clone());
+        let values = match crate::client::complete(&self.server, params) {
+            Ok(result) => result.values,
+            Err(err) => {
+                log::error!("Completion failed: {err:#}");
+                vec![]
+            }
+        };
+        let names: Vec<String> = values.iter().map(|v| v.value.clone()).collect();
+        let candidates = candidates(values, self.dialect, parsed.quote);
+        *self.last.borrow_mut() = Some(LastCompletion {
+            args: parsed.args,
+            values: names
+                .into_iter()
+                .zip(candidates.iter().map(|c| c.replacement.clone()))
+                .collect(),
+        });
+        Ok((parsed.current.start, candidates))
+    }
+
+    fn update(&self, line: &mut LineBuffer, start: usize, elected: &str, cl: &mut Changeset) {
+        let end = line.pos();
+        line.replace(start..end, elected, cl);
+        // The elected text can also be the common prefix of the values.
+        let Some(last) = self.last.borrow_mut().take() else {
+            return;
+        };
+        let Some((value, _)) = last.values.into_iter().find(|(_, r)| r == elected) else {
+            return;
+        };
+        let params = AcceptedParams {
+            args: last.args,
+            value,
+            working_dir: std::env::current_dir().ok(),
+        };
+        if let Err(err) = crate::client::accepted(&self.server, params) {
+            log::warn!("Failed to notify the accepted value: {err:#}");
+        }
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Converts the values into candidates, showing the descriptions aligned
+/// next to the values.
+fn candidates(values: Vec<CompletionValue>, dialect: Dialect, quote: Option<Quote>) -> Vec<Pair> {
+    let width = values
+        .iter()
+        .map(|v| v.display.as_ref().unwrap_or(&v.value).chars().count())
+        .max()
+        .unwrap_or(0);
+    values
+        .into_iter()
+        .map(|v| Pair {
+            display: {
+                let display = v.display.as_deref().unwrap_or(&v.value);
+                match &v.description {
+                    Some(description) => format!("{:width$}  -- {}", display, description),
+                    None => display.to_string(),
+                }
+            },
+            replacement: shell_words::quote(&v.value, dialect, quote),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn aligns_descriptions_and_quotes_replacements() {
+        let values = vec![
+            CompletionValue {
+                value: "--all".into(),
+                description: Some("Show all".into()),
+                ..Default::default()
+            },
+            CompletionValue {
+                value: "my file".into(),
+                description: Some("A file".into()),
+                ..Default::default()
+            },
+        ];
+        let candidates = candidates(values, Dialect::Bash, None);
+        expect_that!(
+            candidates
+                .iter()
+                .map(|c| (c.display.as_str(), c.replacement.as_str()))
+                .collect::<Vec<_>>(),
+            eq(&vec![
+                ("--all    -- Show all", "--all"),
+                ("my file  -- A file", r"my\ file"),
+            ])
+        );
+    }
+}
@@ -1,15 +1,35 @@
-use crate::connection::{ConnRequest, ResponseError, SendError, Transport};
-use crate::types::{CompleteParams, CompleteResult, Error, ShutdownResult};
+use crate::cache::{fnv1a, DiskCache};
+use crate::connection::{ResponseError, Transport};
+use crate::filter::FilterMode;
+use crate::history::History;
+use crate::server::Handler;
+use crate::spec::SpecFormat;
+use crate::system::now;
+use crate::types::{
+    AcceptedParams, CacheControl, CompleteParams, CompleteResult, Error, FilesHint,
+};
+use anyhow::Context;
 use clap::Args;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Args)]
 pub struct RouterArgs {
     /// The configuration path for available completers.
+    ///
+    /// Defaults to ~/.config/command-autocomplete/completers.toml
     config: Option<PathBuf>,
-    // TODO: add a config
+    /// Run as a daemon, accepting connections on the given unix socket
+    /// instead of stdio.
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// Keep the order of the completers, instead of listing the values that
+    /// were accepted often and recently first.
+    #[arg(long)]
+    no_ranking: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -18,109 +38,350 @@ pub struct Completer {
     args: Vec<String>,
 }
 
+impl Completer {
+    /// The command line of the completer, used to identify it.
+    fn name(&self) -> String {
+        std::iter::once(&self.command)
+            .chain(&self.args)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Command {
     name: String,
-    completer: Completer,
+    /// The server completing the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completer: Option<Completer>,
+    /// The spec of the command (see `bridge spec`), used instead of the
+    /// completer. Relative to the directory of the config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spec: Option<PathBuf>,
+    /// The format of the spec (see `bridge spec --format`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spec_format: Option<SpecFormat>,
+    /// Overrides the filtering of the config for the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filter: Option<FilterMode>,
+    /// The caching of the results, for the completers that don't set it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache: Option<CacheControl>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Config {
+    /// How the values are matched with the completed word, unless the
+    /// completer already filtered them.
+    #[serde(default)]
+    pub filter: FilterMode,
     pub command: Vec<Command>,
 }
 
-pub fn run_router(_args: RouterArgs) -> anyhow::Result<()> {
-    let content = std::fs::read_to_string(
-        Path::new(&std::env::var("HOME")?).join(".config/command-autocomplete/completers.toml"),
-    );
-    let config = match content {
-        Ok(content) => toml::from_str(&content)?,
-        // TODO: check the error, only return default on not found
-        Err(_) => Config::default(),
-    };
+fn load_config(path: Option<&Path>) -> anyhow::Result<Config> {
+    if let Some(path) = path {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        return parse_config(&content, path);
+    }
+    let path =
+        Path::new(&std::env::var("HOME")?).join(".config/command-autocomplete/completers.toml");
+    match std::fs::read_to_string(&path) {
+        Ok(content) => parse_config(&content, &path),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn parse_config(content: &str, path: &Path) -> anyhow::Result<Config> {
+    let mut config: Config = toml::from_str(content)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for command in &mut config.command {
+        match (&command.completer, &mut command.spec) {
+            (Some(_), None) => {}
+            (None, Some(spec)) => *spec = dir.join(&*spec),
+            _ => anyhow::bail!(
+                "command {} must have either a completer or a spec",
+                command.name
+            ),
+        }
+    }
+    Ok(config)
+}
+
+pub fn run_router(args: RouterArgs) -> anyhow::Result<()> {
+    let config = load_config(args.config.as_deref())?;
+    let history = (!args.no_ranking).then(History::new);
+    if let Some(socket) = &args.socket {
+        return run_daemon(socket, config, history);
+    }
     let (transport, join_handle) = Transport::stdio();
-    {
-        let (_, receiver) = crate::connection::new_connection(transport);
-        let mut router = Router::new(config);
-        while let Some(req) = receiver.next_request() {
-            match router.handle_request(req) {
-                Ok(LoopAction::Continue) => continue,
-                Ok(LoopAction::Stop) => break,
-                Err(_) => {
-                    log::warn!("the connection closed unexpectedly, stopping the receving loop");
-                    break;
+    let cache = ResultCache::Disk(DiskCache::new("results"));
+    serve(transport, config, history, cache);
+    join_handle.join()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn run_daemon(socket: &Path, config: Config, history: Option<History>) -> anyhow::Result<()> {
+    // A socket left by the previous run would make the bind fail, but only
+    // a socket that no daemon listens on is removed.
+    if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+        use std::os::unix::fs::FileTypeExt;
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", socket.display());
+        }
+        if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+            anyhow::bail!("daemon already running on {}", socket.display());
+        }
+        std::fs::remove_file(socket)
+            .with_context(|| format!("failed to remove stale socket {}", socket.display()))?;
+    }
+    let listener = std::os::unix::net::UnixListener::bind(socket)
+        .with_context(|| format!("failed to listen on {}", socket.display()))?;
+    log::info!("listening on {}", socket.display());
+    // The daemon keeps the results in memory, shared by the connections.
+    let cache = ResultCache::Memory(Default::default());
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("failed to accept the connection: {err}");
+                continue;
+            }
+        };
+        let config = config.clone();
+        let history = history.clone();
+        let cache = cache.clone();
+        std::thread::spawn(move || {
+            let (transport, join_handle) = match Transport::unix(stream) {
+                Ok(t) => t,
+                Err(err) => {
+                    log::warn!("failed to set up the connection: {err}");
+                    return;
                 }
             };
-        }
+            serve(transport, config, history, cache);
+            if let Err(err) = join_handle.join() {
+                log::warn!("connection threads failed: {:?}", err);
+            }
+        });
     }
-    join_handle.join()?;
     Ok(())
 }
 
-struct Router {
-    config: Config,
+#[cfg(not(unix))]
+fn run_daemon(_socket: &Path, _config: Config, _history: Option<History>) -> anyhow::Result<()> {
+    anyhow::bail!("router daemon is only supported on unix")
 }
 
-enum LoopAction {
-    Continue,
-    Stop,
+/// Handles the requests on the connection until it is shut down.
+fn serve(transport: Transport, config: Config, history: Option<History>, cache: ResultCache) {
+    crate::server::serve_handler(transport, Router::new(config, history, cache));
 }
 
-impl Router {
-    fn new(config: Config) -> Self {
-        Router { config }
+/// The results of the completers, reused as allowed by their [CacheControl].
+#[derive(Clone)]
+enum ResultCache {
+    Memory(Arc<Mutex<HashMap<String, CachedResult>>>),
+    Disk(DiskCache),
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct CachedResult {
+    /// The time (in seconds since the unix epoch) after which the result is
+    /// not used.
+    expires: u64,
+    working_dir: Option<PathBuf>,
+    /// The hash of the values of the environment variables of the cache
+    /// control, as they may be secrets.
+    envs: u64,
+    result: CompleteResult,
+}
+
+impl ResultCache {
+    /// Returns the result cached for the request, checking all the prefixes
+    /// of the args, as the result may depend only on some of them.
+    fn get(&self, params: &CompleteParams) -> Option<CompleteResult> {
+        let now = now();
+        (1..=params.args.len()).rev().find_map(|len| {
+            let cached = self.load(&cache_key(&params.args[..len]))?;
+            let control = cached.result.cache.as_ref()?;
+            let valid = key_len(control, params) == len
+                && cached.expires > now
+                && (control.any_working_dir || cached.working_dir == params.working_dir)
+                && cached.envs == env_values(control, params);
+            valid.then_some(cached.result)
+        })
     }
 
-    fn handle_request(&mut self, req: ConnRequest) -> Result<LoopAction, SendError> {
-        match req.inner().method.as_str() {
-            "complete" => match serde_json::from_value(req.inner().params.clone()) {
-                Ok(params) => {
-                    req.reply(self.handle_complete_request(params))?;
-                }
-                Err(err) => {
-                    req.reply_err(Error::invalid_request(format!(
-                        "invalid params for complete request: {err}"
-                    )))?;
-                }
-            },
-            "shutdown" => {
-                req.reply_ok(ShutdownResult {})?;
-                return Ok(LoopAction::Stop);
-            }
-            _ => {
-                let method = req.inner().method.clone();
-                req.reply_err(Error {
-                    code: "UNKNOWN_REQUEST".to_string(),
-                    message: format!("method {} is not recognized", method),
-                })?;
+    fn put(&self, params: &CompleteParams, result: &CompleteResult) {
+        let Some(control) = &result.cache else {
+            return;
+        };
+        let key = cache_key(&params.args[..key_len(control, params)]);
+        let cached = CachedResult {
+            expires: now().saturating_add(control.ttl),
+            working_dir: params.working_dir.clone(),
+            envs: env_values(control, params),
+            result: result.clone(),
+        };
+        match self {
+            ResultCache::Memory(results) => {
+                let mut results = results.lock().unwrap();
+                let now = now();
+                results.retain(|_, cached| cached.expires > now);
+                results.insert(key, cached);
             }
+            ResultCache::Disk(cache) => cache.put_with_ttl(&key, &cached, control.ttl),
         }
-        Ok(LoopAction::Continue)
     }
 
-    fn completer(&self, params: &CompleteParams) -> Option<std::process::Command> {
+    fn load(&self, key: &str) -> Option<CachedResult> {
+        match self {
+            ResultCache::Memory(results) => results.lock().unwrap().get(key).cloned(),
+            ResultCache::Disk(cache) => cache.get(key),
+        }
+    }
+}
+
+/// The number of the leading args the result is cached for.
+fn key_len(control: &CacheControl, params: &CompleteParams) -> usize {
+    control
+        .args
+        .map_or(params.args.len(), |len| len.min(params.args.len()))
+}
+
+fn cache_key(args: &[String]) -> String {
+    args.join("\0")
+}
+
+fn env_values(control: &CacheControl, params: &CompleteParams) -> u64 {
+    let values: Vec<Option<String>> = control.envs.iter().map(|name| params.env(name)).collect();
+    fnv1a(&serde_json::to_vec(&values).unwrap_or_default())
+}
+
+struct Router {
+    config: Config,
+    /// The history of the accepted values, used to rank the values (unless
+    /// the ranking is disabled).
+    history: Option<History>,
+    cache: ResultCache,
+}
+
+impl Handler for Router {
+    fn complete(&mut self, params: CompleteParams) -> Result<CompleteResult, Error> {
+        Router::complete(self, params)
+    }
+
+    fn accepted(&mut self, params: AcceptedParams) {
+        if let Some(history) = &self.history {
+            history.record(&params);
+        }
+    }
+}
+
+impl Router {
+    fn new(config: Config, history: Option<History>, cache: ResultCache) -> Self {
+        Router {
+            config,
+            history,
+            cache,
+        }
+    }
+
+    fn command(&self, params: &CompleteParams) -> Option<&Command> {
         if params.args.is_empty() {
             return None;
         }
-        for command in &self.config.command {
-            if command.name != params.args[0] {
-                continue;
+        self.config
+            .command
+            .iter()
+            .find(|command| command.name == params.args[0])
+    }
+
+    /// Completes the command, listing the paths matching the files hint of the
+    /// result, filtering the values by the completed word, and ranking them by
+    /// the history (unless the completer requires to keep their order).
+    fn complete(&mut self, params: CompleteParams) -> Result<CompleteResult, Error> {
+        let mode = self
+            .command(&params)
+            .and_then(|command| command.filter)
+            .unwrap_or(self.config.filter);
+        let word = match params.args.len() {
+            // The command names are not completed by the completers.
+            0 | 1 => String::new(),
+            _ => params.args.last().cloned().unwrap_or_default(),
+        };
+        let mut request = params.clone();
+        if mode.beyond_prefix() && !word.is_empty() {
+            if let Some(last) = request.args.last_mut() {
+                last.truncate(crate::filter::word_context(&word).len());
             }
-            let mut cmd = std::process::Command::new(&command.completer.command);
-            cmd.args(&command.completer.args);
-            return Some(cmd);
         }
-        None
+        let cached = match request.refresh {
+            true => None,
+            false => self.cache.get(&request),
+        };
+        let mut result = match cached {
+            Some(result) => result,
+            None => {
+                let mut result = self.handle_complete_request(request.clone())?;
+                if result.cache.is_none() {
+                    result.cache = self.command(&request).and_then(|c| c.cache.clone());
+                }
+                self.cache.put(&request, &result);
+                result
+            }
+        };
+        crate::path::expand_files_hint(&mut result, &request);
+        // The values filtered by the completer (given only the context of the
+        // word) still need to be matched with the whole word.
+        if !result.filtered || mode.beyond_prefix() {
+            crate::filter::filter(mode, &word, &mut result.values, result.keep_order);
+            result.filtered = mode != FilterMode::None;
+        }
+        if let Some(history) = self.history.as_ref().filter(|_| !result.keep_order) {
+            history.rank(&params, &mut result.values);
+        }
+        // Trimmed after the ranking, so that the best values are kept.
+        if let Some(max) = params.max_results.filter(|max| result.values.len() > *max) {
+            result.values.truncate(max);
+            result.is_incomplete = true;
+        }
+        Ok(result)
     }
 
     fn handle_complete_request(&mut self, params: CompleteParams) -> Result<CompleteResult, Error> {
-        let Some(mut command) = self.completer(&params) else {
-            if !params.args.is_empty() {
-                log::info!("completer for command {} not found", params.args[0]);
+        let Some(command) = self.command(&params) else {
+            if params.args.len() < 2 {
+                return Ok(CompleteResult::default());
             }
-            // TODO: handle this better
-            return Ok(CompleteResult { values: vec![] });
+            log::info!("completer for command {} not found", params.args[0]);
+            // The args of the unknown commands are most often paths.
+            return Ok(CompleteResult {
+                completer: Some("paths".to_string()),
+                files: Some(FilesHint::default()),
+                ..Default::default()
+            });
+        };
+        if let Some(spec) = &command.spec {
+            let name = format!("spec {}", spec.display());
+            return crate::spec::complete_file(spec, command.spec_format, params).map(
+                |mut result| {
+                    result.completer.get_or_insert(name);
+                    result
+                },
+            );
+        }
+        let Some(completer) = &command.completer else {
+            return Ok(CompleteResult::default());
         };
+        let completer_name = completer.name();
+        let mut command = std::process::Command::new(&completer.command);
+        command.args(&completer.args);
+        params.apply_to(&mut command);
         log::debug!("starting external completer: {:?}", command);
 
         // TODO: unwrap
@@ -151,29 +412,171 @@ impl Router {
             }
             log::debug!("receiver finished");
         });
-        // TODO: unwrap
         log::debug!("sending complete request to sub process");
-        let res = sender.send::<CompleteResult>("complete", params).unwrap();
-        log::debug!("waiting for complete response");
-        let res = res.wait().map_err(|e| match e {
-            ResponseError::Err(e) => e,
-            ResponseError::ChannelClosed => {
-                Error::internal("subprocess closed connection before providing completions")
-            }
-            ResponseError::DeserializationError(err) => Error::internal(format!(
-                "subprocess returned response that failed deserialization, error: {err}"
-            )),
-        });
+        let res = sender
+            .send::<CompleteResult>("complete", params)
+            .map_err(|_| Error::internal("subprocess closed connection before the request"))
+            .and_then(|res| {
+                log::debug!("waiting for complete response");
+                res.wait().map_err(|e| match e {
+                    ResponseError::Err(e) => e,
+                    ResponseError::ChannelClosed => {
+                        Error::internal("subprocess closed connection before providing completions")
+                    }
+                    ResponseError::DeserializationError(err) => Error::internal(format!(
+                        "subprocess returned response that failed deserialization, error: {err}"
+                    )),
+                })
+            });
         log::debug!("received response: {:?}", res.is_ok());
 
-        // TODO: handle unwrap
-        sender.shutdown().unwrap().wait().unwrap();
+        let shutdown = sender
+            .shutdown()
+            .map_err(anyhow::Error::from)
+            .and_then(|handle| Ok(handle.wait()?));
+        if let Err(err) = shutdown {
+            log::warn!("subprocess shutdown failed, will kill it: {err}");
+            if let Err(err) = child.kill() {
+                log::warn!("failed to kill subprocess: {err}");
+            }
+        }
 
-        join_handle.join().unwrap();
-        recv_join_handle.join().unwrap();
-        child.wait().unwrap();
+        if let Err(err) = child.wait() {
+            log::warn!("failed to wait for the subprocess: {err}");
+        }
+        if let Err(err) = join_handle.join() {
+            log::warn!("connection threads failed: {:?}", err);
+        }
+        if let Err(err) = recv_join_handle.join() {
+            log::warn!("receiving thread failed: {:?}", err);
+        }
 
         // TODO: exit cleanly
-        res
+        res.map(|mut result| {
+            result.completer.get_or_insert(completer_name);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CompletionValue, EnvironmentVariable};
+    use googletest::prelude::*;
+
+    fn params(args: &[&str], working_dir: &str, kubeconfig: &str) -> CompleteParams {
+        CompleteParams {
+            args: args.iter().map(|a| a.to_string()).collect(),
+            working_dir: Some(working_dir.into()),
+            envs: vec![EnvironmentVariable {
+                name: "KUBECONFIG".into(),
+                value: kubeconfig.into(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn result(value: &str, cache: CacheControl) -> CompleteResult {
+        CompleteResult {
+            values: vec![CompletionValue {
+                value: value.into(),
+                ..Default::default()
+            }],
+            cache: Some(cache),
+            ..Default::default()
+        }
+    }
+
+    fn cached(cache: &ResultCache, params: &CompleteParams) -> Option<String> {
+        cache
+            .get(params)
+            .map(|result| result.values[0].value.clone())
+    }
+
+    #[gtest]
+    fn trims_results_to_max_results() {
+        let config = parse_config(
+            r#"
+            [[command]]
+            name = "mytool"
+            spec = "mytool.yaml"
+            "#,
+            Path::new("testdata/spec/completers.toml"),
+        )
+        .unwrap();
+        let mut router = Router::new(config, None, ResultCache::Memory(Default::default()));
+        let mut request = params(&["mytool", ""], ".", "a");
+
+        let result = router.complete(request.clone()).unwrap();
+        expect_that!(result.values.len(), eq(2));
+        expect_that!(result.is_incomplete, eq(false));
+        request.max_results = Some(1);
+        let result = router.complete(request).unwrap();
+        expect_that!(result.values.len(), eq(1));
+        expect_that!(result.is_incomplete, eq(true));
+    }
+
+    #[gtest]
+    fn caches_results_by_scope() {
+        let cache = ResultCache::Memory(Default::default());
+        let pods = params(&["kubectl", "get", "pods", "my"], "/src", "a");
+        cache.put(
+            &pods,
+            &result(
+                "my-pod",
+                CacheControl {
+                    ttl: 60,
+                    args: Some(3),
+                    any_working_dir: true,
+                    envs: vec!["KUBECONFIG".into()],
+                },
+            ),
+        );
+        expect_that!(cached(&cache, &pods), some(eq("my-pod")));
+        // The args after the prefix and the working directory can change.
+        expect_that!(
+            cached(
+                &cache,
+                &params(&["kubectl", "get", "pods", ""], "/tmp", "a")
+            ),
+            some(eq("my-pod"))
+        );
+        expect_that!(
+            cached(&cache, &params(&["kubectl", "get", "svc", ""], "/src", "a")),
+            none()
+        );
+        expect_that!(
+            cached(
+                &cache,
+                &params(&["kubectl", "get", "pods", ""], "/src", "b")
+            ),
+            none()
+        );
+
+        let files = params(&["tool", "build", ""], "/src", "a");
+        cache.put(
+            &files,
+            &result(
+                "a.rs",
+                CacheControl {
+                    ttl: 60,
+                    ..Default::default()
+                },
+            ),
+        );
+        expect_that!(cached(&cache, &files), some(eq("a.rs")));
+        expect_that!(
+            cached(&cache, &params(&["tool", "build", ""], "/tmp", "a")),
+            none()
+        );
+        expect_that!(
+            cached(&cache, &params(&["tool", "build", "x"], "/src", "a")),
+            none()
+        );
+
+        let expired = params(&["tool", "run", ""], "/src", "a");
+        cache.put(&expired, &result("now", CacheControl::default()));
+        expect_that!(cached(&cache, &expired), none());
     }
 }
@@ -0,0 +1,102 @@
+use crate::connection::{ConnRequest, Incoming, SendError, Transport};
+use crate::types::{AcceptedParams, CompleteParams, CompleteResult, Error, ShutdownResult};
+
+/// Handles the messages received by the server. It is implemented by the
+/// closures answering the `complete` requests.
+pub trait Handler {
+    fn complete(&mut self, params: CompleteParams) -> Result<CompleteResult, Error>;
+
+    /// Called when the client notifies that the user inserted one of the
+    /// completed values. Ignored by default.
+    fn accepted(&mut self, _params: AcceptedParams) {}
+}
+
+impl<F> Handler for F
+where
+    F: FnMut(CompleteParams) -> Result<CompleteResult, Error>,
+{
+    fn complete(&mut self, params: CompleteParams) -> Result<CompleteResult, Error> {
+        self(params)
+    }
+}
+
+/// Runs the Command Autocomplete Server on stdio, answering the `complete`
+/// requests with the handler, until the connection is shut down.
+pub fn serve<F>(handler: F) -> anyhow::Result<()>
+where
+    F: FnMut(CompleteParams) -> Result<CompleteResult, Error>,
+{
+    let (transport, join_handle) = Transport::stdio();
+    serve_transport(transport, handler);
+    join_handle.join()?;
+    Ok(())
+}
+
+/// Answers the requests received on the transport, until the connection is
+/// shut down.
+pub fn serve_transport<F>(transport: Transport, handler: F)
+where
+    F: FnMut(CompleteParams) -> Result<CompleteResult, Error>,
+{
+    serve_handler(transport, handler);
+}
+
+/// Like [serve_transport], passing also the notifications to the handler.
+pub fn serve_handler<H: Handler>(transport: Transport, mut handler: H) {
+    let (_, receiver) = crate::connection::new_connection(transport);
+    while let Some(incoming) = receiver.next_incoming() {
+        let req = match incoming {
+            Incoming::Request(req) => req,
+            Incoming::Notification(notification) => {
+                match notification.method.as_str() {
+                    "accepted" => match serde_json::from_value(notification.params) {
+                        Ok(params) => handler.accepted(params),
+                        Err(err) => log::warn!("invalid params for accepted notification: {err}"),
+                    },
+                    method => log::debug!("ignoring unknown notification {method}"),
+                }
+                continue;
+            }
+        };
+        match handle_request(req, &mut handler) {
+            Ok(LoopAction::Continue) => continue,
+            Ok(LoopAction::Stop) => break,
+            Err(_) => {
+                log::warn!("the connection closed unexpectedly, stopping the receving loop");
+                break;
+            }
+        }
+    }
+}
+
+enum LoopAction {
+    Continue,
+    Stop,
+}
+
+fn handle_request<H: Handler>(req: ConnRequest, handler: &mut H) -> Result<LoopAction, SendError> {
+    match req.inner().method.as_str() {
+        "complete" => match serde_json::from_value(req.inner().params.clone()) {
+            Ok(params) => {
+                req.reply(handler.complete(params))?;
+            }
+            Err(err) => {
+                req.reply_err(Error::invalid_request(format!(
+                    "invalid params for complete request: {err}"
+                )))?;
+            }
+        },
+        "shutdown" => {
+            req.reply_ok(ShutdownResult {})?;
+            return Ok(LoopAction::Stop);
+        }
+        _ => {
+            let method = req.inner().method.clone();
+            req.reply_err(Error {
+                code: "UNKNOWN_REQUEST".to_string(),
+                message: format!("method {} is not recognized", method),
+            })?;
+        }
+    }
+    Ok(LoopAction::Continue)
+}
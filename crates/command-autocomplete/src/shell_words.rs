@@ -0,0 +1,780 @@
+//! Splitting command lines into args and quoting completion values, following
+//! the rules of a given shell.
+
+use std::ops::Range;
+
+/// The shell whose syntax is used for splitting and quoting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Dialect {
+    Bash,
+    Zsh,
+    Fish,
+    Nushell,
+    Powershell,
+    Elvish,
+}
+
+/// The kind of quotes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quote {
+    /// `'...'`
+    Single,
+    /// `"..."`
+    Double,
+    /// `` `...` `` (nushell raw string)
+    Backtick,
+    /// `$'...'` (bash and zsh ANSI-C quoting)
+    AnsiC,
+}
+
+/// The command line split into args, as seen at the cursor.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedLine {
+    /// The args of the command under the cursor, up to the cursor. The last
+    /// arg is the (possibly empty) part of the token under the cursor.
+    pub args: Vec<String>,
+    /// The range of the token under the cursor in the line (including the
+    /// part after the cursor). This is the range to replace with the
+    /// completion value.
+    pub current: Range<usize>,
+    /// The quote that is open at the cursor.
+    pub quote: Option<Quote>,
+}
+
+/// Parses the line up to the cursor (a byte offset in the line).
+///
+/// Only the args of the command under the cursor are returned, so for
+/// `ls | grep fo` the args are `["grep", "fo"]`.
+pub fn parse(line: &str, cursor: usize, dialect: Dialect) -> ParsedLine {
+    let mut cursor = cursor.min(line.len());
+    while !line.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+    let mut lexer = Lexer::new(line, dialect);
+    let mut args = vec![];
+    while let Some(event) = lexer.next_event(cursor) {
+        match event {
+            Event::Token(token) => args.push(token.value),
+            Event::Separator => args.clear(),
+            Event::Cursor => break,
+        }
+    }
+    let Some(current) = lexer.current.take() else {
+        // The cursor is not inside of a token, so a new one is started.
+        args.push(String::new());
+        return ParsedLine {
+            args,
+            current: cursor..cursor,
+            quote: None,
+        };
+    };
+    let quote = lexer.quote;
+    args.push(current.value);
+    // Finish the token under the cursor, to find where it ends.
+    let mut rest = Lexer::new(line, dialect);
+    rest.pos = cursor;
+    rest.quote = quote;
+    rest.escape = lexer.escape;
+    rest.current = Some(Token {
+        value: String::new(),
+        start: current.start,
+    });
+    rest.next_event(line.len() + 1);
+    let end = rest.token_end;
+    ParsedLine {
+        args,
+        current: current.start..end,
+        quote,
+    }
+}
+
+/// Splits the line into args, ignoring the separators between commands.
+pub fn split(line: &str, dialect: Dialect) -> Vec<String> {
+    let mut lexer = Lexer::new(line, dialect);
+    let mut args = vec![];
+    while let Some(event) = lexer.next_event(line.len() + 1) {
+        if let Event::Token(token) = event {
+            args.push(token.value);
+        }
+    }
+    if let Some(token) = lexer.current.take() {
+        args.push(token.value);
+    }
+    args
+}
+
+/// Quotes the value, so that it can be inserted into the command line as a
+/// single arg.
+///
+/// If `quote` is set (usually the quote open at the cursor), the value is
+/// quoted with it, otherwise it is quoted only when needed. Trailing
+/// whitespace is kept unquoted, as completers use it to request a separator
+/// after the value.
+pub fn quote(value: &str, dialect: Dialect, quote: Option<Quote>) -> String {
+    let trimmed = value.trim_end();
+    let suffix = &value[trimmed.len()..];
+    let value = trimmed;
+    if value.is_empty() {
+        return suffix.to_string();
+    }
+    let quote = match quote {
+        Some(quote) => quote,
+        None if !needs_quoting(value, dialect) => return format!("{value}{suffix}"),
+        None => match dialect {
+            Dialect::Bash | Dialect::Zsh | Dialect::Fish => {
+                return format!("{}{suffix}", escape(value, dialect));
+            }
+            Dialect::Nushell if !value.contains('`') => Quote::Backtick,
+            Dialect::Nushell => Quote::Double,
+            Dialect::Powershell | Dialect::Elvish => Quote::Single,
+        },
+    };
+    format!("{}{suffix}", quote_with(value, dialect, quote))
+}
+
+fn needs_quoting(value: &str, dialect: Dialect) -> bool {
+    let special: &[char] = match dialect {
+        Dialect::Bash | Dialect::Zsh => &[
+            '|', '&', ';', '<', '>', '(', ')', '$', '`', '\\', '"', '\'', '*', '?', '[', ']', '{',
+            '}', '!',
+        ],
+        Dialect::Fish => &[
+            '|', '&', ';', '<', '>', '(', ')', '$', '\\', '"', '\'', '*', '?', '{', '}',
+        ],
+        Dialect::Nushell => &['|', ';', '(', ')', '[', ']', '{', '}', '$', '"', '\'', '`'],
+        Dialect::Powershell => &[
+            '|', '&', ';', ',', '<', '>', '(', ')', '{', '}', '$', '`', '"', '\'', '@',
+        ],
+        Dialect::Elvish => &[
+            '|', '&', ';', '<', '>', '(', ')', '[', ']', '{', '}', '$', '\\', '"', '\'', '*', '?',
+            '^',
+        ],
+    };
+    value.starts_with('#') || value.contains(|c: char| c.is_whitespace() || special.contains(&c))
+}
+
+/// Escapes the special characters with a backslash.
+fn escape(value: &str, dialect: Dialect) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        if c == '\n' {
+            // Newline can't be escaped with backslash (it means line continuation).
+            out.push_str(&quote_with("\n", dialect, Quote::Single));
+            continue;
+        }
+        if c.is_whitespace() || needs_quoting(&c.to_string(), dialect) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn quote_with(value: &str, dialect: Dialect, quote: Quote) -> String {
+    let mut out = String::new();
+    match (quote, dialect) {
+        (Quote::Single, Dialect::Bash | Dialect::Zsh) => {
+            out.push('\'');
+            out.push_str(&value.replace('\'', r"'\''"));
+            out.push('\'');
+        }
+        (Quote::Single, Dialect::Fish) => {
+            out.push('\'');
+            out.push_str(&value.replace('\\', r"\\").replace('\'', r"\'"));
+            out.push('\'');
+        }
+        (Quote::Single, Dialect::Powershell | Dialect::Elvish) => {
+            out.push('\'');
+            out.push_str(&value.replace('\'', "''"));
+            out.push('\'');
+        }
+        (Quote::Single, Dialect::Nushell) if !value.contains('\'') => {
+            out.push('\'');
+            out.push_str(value);
+            out.push('\'');
+        }
+        (Quote::Backtick, Dialect::Nushell) if !value.contains('`') => {
+            out.push('`');
+            out.push_str(value);
+            out.push('`');
+        }
+        (Quote::Double, Dialect::Bash | Dialect::Zsh | Dialect::Fish) => {
+            let special: &[char] = if dialect == Dialect::Fish {
+                &['"', '\\', '$']
+            } else {
+                &['"', '\\', '$', '`']
+            };
+            out.push('"');
+            for c in value.chars() {
+                if special.contains(&c) {
+                    out.push('\\');
+                }
+                out.push(c);
+            }
+            out.push('"');
+        }
+        (Quote::Double, Dialect::Powershell) => {
+            out.push('"');
+            for c in value.chars() {
+                if ['"', '`', '$'].contains(&c) {
+                    out.push('`');
+                }
+                out.push(c);
+            }
+            out.push('"');
+        }
+        (Quote::AnsiC, Dialect::Bash | Dialect::Zsh) => {
+            out.push_str("$'");
+            for c in value.chars() {
+                match c {
+                    '\\' | '\'' => {
+                        out.push('\\');
+                        out.push(c);
+                    }
+                    '\n' => out.push_str(r"\n"),
+                    '\t' => out.push_str(r"\t"),
+                    c => out.push(c),
+                }
+            }
+            out.push('\'');
+        }
+        (Quote::Double, Dialect::Nushell | Dialect::Elvish) => {
+            push_backslash_quoted(&mut out, value)
+        }
+        // The quote is not supported in this dialect (or can't represent the
+        // value), so we fall back to the quote that can represent anything.
+        (_, Dialect::Nushell | Dialect::Elvish) => push_backslash_quoted(&mut out, value),
+        (_, Dialect::Powershell) => return quote_with(value, dialect, Quote::Single),
+        (_, Dialect::Bash | Dialect::Zsh | Dialect::Fish) => {
+            return quote_with(value, dialect, Quote::Double)
+        }
+    }
+    out
+}
+
+fn push_backslash_quoted(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str(r"\n"),
+            '\t' => out.push_str(r"\t"),
+            '\r' => out.push_str(r"\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Token {
+    value: String,
+    start: usize,
+}
+
+enum Event {
+    Token(Token),
+    /// A separator between commands (e.g. `|` or `;`).
+    Separator,
+    /// The cursor was reached.
+    Cursor,
+}
+
+struct Lexer<'a> {
+    line: &'a str,
+    dialect: Dialect,
+    pos: usize,
+    current: Option<Token>,
+    quote: Option<Quote>,
+    /// The start of the escape character whose escaped character is not
+    /// handled yet, as the cursor is between them.
+    escape: Option<usize>,
+    /// The end of the last finished token.
+    token_end: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(line: &'a str, dialect: Dialect) -> Self {
+        Lexer {
+            line,
+            dialect,
+            pos: 0,
+            current: None,
+            quote: None,
+            escape: None,
+            token_end: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.line[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn push(&mut self, c: char, start: usize) {
+        self.current
+            .get_or_insert_with(|| Token {
+                value: String::new(),
+                start,
+            })
+            .value
+            .push(c);
+    }
+
+    fn finish_token(&mut self, end: usize) -> Option<Event> {
+        self.token_end = end;
+        self.current.take().map(Event::Token)
+    }
+
+    /// Returns the next event, or None when the end of line is reached.
+    fn next_event(&mut self, cursor: usize) -> Option<Event> {
+        loop {
+            if self.pos == cursor {
+                // Move past the cursor, so that lexing can be continued.
+                return Some(Event::Cursor);
+            }
+            if let Some(start) = self.escape.take() {
+                self.escaped(start);
+                continue;
+            }
+            let start = self.pos;
+            let Some(c) = self.bump() else {
+                self.token_end = self.pos;
+                return None;
+            };
+            match self.quote {
+                None => {
+                    if let Some(event) = self.unquoted(c, start) {
+                        return Some(event);
+                    }
+                }
+                Some(quote) => self.quoted(quote, c, start),
+            }
+        }
+    }
+
+    fn unquoted(&mut self, c: char, start: usize) -> Option<Event> {
+        let dialect = self.dialect;
+        match c {
+            c if c.is_whitespace() && c != '\n' => self.finish_token(start),
+            '\n' | ';' | '|' | '&' => {
+                if let Some(event) = self.finish_token(start) {
+                    // Handle the separator on the next call.
+                    self.pos = start;
+                    return Some(event);
+                }
+                Some(Event::Separator)
+            }
+            '#' if self.current.is_none() => {
+                // The rest of the line is a comment.
+                self.pos = self.line.len();
+                None
+            }
+            '\\' if matches!(dialect, Dialect::Bash | Dialect::Zsh | Dialect::Fish) => {
+                self.escape(start);
+                None
+            }
+            '`' if dialect == Dialect::Powershell => {
+                self.escape(start);
+                None
+            }
+            '$' if matches!(dialect, Dialect::Bash | Dialect::Zsh) && self.peek() == Some('\'') => {
+                self.bump();
+                self.open(Quote::AnsiC, start);
+                None
+            }
+            '\'' => {
+                self.open(Quote::Single, start);
+                None
+            }
+            '"' => {
+                self.open(Quote::Double, start);
+                None
+            }
+            '`' if dialect == Dialect::Nushell => {
+                self.open(Quote::Backtick, start);
+                None
+            }
+            c => {
+                self.push(c, start);
+                None
+            }
+        }
+    }
+
+    fn open(&mut self, quote: Quote, start: usize) {
+        self.current.get_or_insert_with(|| Token {
+            value: String::new(),
+            start,
+        });
+        self.quote = Some(quote);
+    }
+
+    fn quoted(&mut self, quote: Quote, c: char, start: usize) {
+        let dialect = self.dialect;
+        match (quote, c) {
+            (Quote::Single, '\'')
+                if matches!(dialect, Dialect::Powershell | Dialect::Elvish)
+                    && self.peek() == Some('\'') =>
+            {
+                self.bump();
+                self.push('\'', start);
+            }
+            (Quote::Single | Quote::AnsiC, '\'')
+            | (Quote::Backtick, '`')
+            | (Quote::Double, '"') => {
+                if quote == Quote::Double
+                    && dialect == Dialect::Powershell
+                    && self.peek() == Some('"')
+                {
+                    self.bump();
+                    self.push('"', start);
+                } else {
+                    self.quote = None;
+                }
+            }
+            (Quote::Single, '\\') if dialect == Dialect::Fish => self.escape(start),
+            (Quote::Double, '\\') | (Quote::AnsiC, '\\')
+                if !matches!(dialect, Dialect::Powershell) =>
+            {
+                self.escape(start)
+            }
+            (Quote::Double, '`') if dialect == Dialect::Powershell => self.escape(start),
+            (_, c) => self.push(c, start),
+        }
+    }
+
+    /// Starts an escape. The escaped character is handled on the next step,
+    /// after the cursor event when the cursor is right after the escape
+    /// character.
+    fn escape(&mut self, start: usize) {
+        // A line continuation doesn't start a token.
+        if self.peek() != Some('\n') {
+            self.current.get_or_insert_with(|| Token {
+                value: String::new(),
+                start,
+            });
+        }
+        self.escape = Some(start);
+    }
+
+    /// Handles the character escaped by the escape character at the start.
+    fn escaped(&mut self, start: usize) {
+        let dialect = self.dialect;
+        match self.quote {
+            None => match self.bump() {
+                // Line continuation.
+                Some('\n') | None => {}
+                Some(c) if matches!(dialect, Dialect::Bash | Dialect::Zsh) => self.push(c, start),
+                Some(c) => self.push(unescape(c), start),
+            },
+            Some(Quote::Single) => match self.peek() {
+                Some(n @ ('\'' | '\\')) => {
+                    self.bump();
+                    self.push(n, start);
+                }
+                _ => self.push('\\', start),
+            },
+            Some(Quote::Double)
+                if matches!(dialect, Dialect::Bash | Dialect::Zsh | Dialect::Fish) =>
+            {
+                let escapable: &[char] = if dialect == Dialect::Fish {
+                    &['"', '\\', '$', '\n']
+                } else {
+                    &['"', '\\', '$', '`', '\n']
+                };
+                match self.peek() {
+                    Some('\n') => {
+                        self.bump();
+                    }
+                    Some(n) if escapable.contains(&n) => {
+                        self.bump();
+                        self.push(n, start);
+                    }
+                    _ => self.push('\\', start),
+                }
+            }
+            Some(_) => {
+                if let Some(n) = self.bump() {
+                    self.push(unescape(n), start);
+                }
+            }
+        }
+    }
+}
+
+/// Translates the character following an escape character (e.g. `n` in `\n`).
+fn unescape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        'e' => '\x1b',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn args(line: &str, dialect: Dialect) -> Vec<String> {
+        parse(line, line.len(), dialect).args
+    }
+
+    #[gtest]
+    fn parses_simple_line() {
+        expect_that!(
+            parse("git checkout ma", 15, Dialect::Bash),
+            eq(&ParsedLine {
+                args: vec!["git".into(), "checkout".into(), "ma".into()],
+                current: 13..15,
+                quote: None,
+            })
+        );
+    }
+
+    #[gtest]
+    fn stops_at_cursor_after_escape_character() {
+        expect_that!(
+            parse("ls a\\b c", 5, Dialect::Bash),
+            eq(&ParsedLine {
+                args: vec!["ls".into(), "a".into()],
+                current: 3..6,
+                quote: None,
+            })
+        );
+        expect_that!(
+            parse("ls \\b", 4, Dialect::Bash),
+            eq(&ParsedLine {
+                args: vec!["ls".into(), "".into()],
+                current: 3..5,
+                quote: None,
+            })
+        );
+        expect_that!(
+            parse("ls \"a\\\"b\"", 6, Dialect::Zsh),
+            eq(&ParsedLine {
+                args: vec!["ls".into(), "a".into()],
+                current: 3..9,
+                quote: Some(Quote::Double),
+            })
+        );
+        expect_that!(
+            parse("ls a`b", 5, Dialect::Powershell),
+            eq(&ParsedLine {
+                args: vec!["ls".into(), "a".into()],
+                current: 3..6,
+                quote: None,
+            })
+        );
+        expect_that!(
+            args("ls a\\b", Dialect::Bash),
+            elements_are![eq("ls"), eq("ab")]
+        );
+    }
+
+    #[gtest]
+    fn starts_new_token_after_whitespace() {
+        expect_that!(
+            parse("git  ", 5, Dialect::Bash),
+            eq(&ParsedLine {
+                args: vec!["git".into(), "".into()],
+                current: 5..5,
+                quote: None,
+            })
+        );
+    }
+
+    #[gtest]
+    fn cursor_inside_of_token() {
+        expect_that!(
+            parse("git checkout main --", 15, Dialect::Bash),
+            eq(&ParsedLine {
+                args: vec!["git".into(), "checkout".into(), "ma".into()],
+                current: 13..17,
+                quote: None,
+            })
+        );
+    }
+
+    #[gtest]
+    fn tracks_unterminated_quote() {
+        expect_that!(
+            parse("cat 'my fi", 10, Dialect::Bash),
+            eq(&ParsedLine {
+                args: vec!["cat".into(), "my fi".into()],
+                current: 4..10,
+                quote: Some(Quote::Single),
+            })
+        );
+        expect_that!(
+            parse("cat \"my fi\" x", 8, Dialect::Fish),
+            eq(&ParsedLine {
+                args: vec!["cat".into(), "my ".into()],
+                current: 4..11,
+                quote: Some(Quote::Double),
+            })
+        );
+    }
+
+    #[gtest]
+    fn only_args_of_current_command() {
+        expect_that!(
+            args("ls | grep fo", Dialect::Bash),
+            eq(&vec!["grep".to_string(), "fo".to_string()])
+        );
+        expect_that!(
+            args("cd x; ls", Dialect::Nushell),
+            eq(&vec!["ls".to_string()])
+        );
+    }
+
+    #[gtest]
+    fn bash_quoting_rules() {
+        expect_that!(
+            args(r#"a b\ c 'd\e' "f\"\g" $'h\ti'"#, Dialect::Bash),
+            eq(&vec![
+                "a".to_string(),
+                "b c".into(),
+                r"d\e".into(),
+                r#"f"\g"#.into(),
+                "h\ti".into(),
+            ])
+        );
+    }
+
+    #[gtest]
+    fn fish_quoting_rules() {
+        expect_that!(
+            args(r#"a b\ c 'd\'\e' "f\"\g""#, Dialect::Fish),
+            eq(&vec![
+                "a".to_string(),
+                "b c".into(),
+                r"d'\e".into(),
+                r#"f"\g"#.into(),
+            ])
+        );
+    }
+
+    #[gtest]
+    fn nushell_quoting_rules() {
+        expect_that!(
+            args(r#"a b\c 'd\e' "f\"\t" `g h`"#, Dialect::Nushell),
+            eq(&vec![
+                "a".to_string(),
+                r"b\c".into(),
+                r"d\e".into(),
+                "f\"\t".into(),
+                "g h".into(),
+            ])
+        );
+    }
+
+    #[gtest]
+    fn powershell_quoting_rules() {
+        expect_that!(
+            args(r#"a b` c 'd''e' "f`"""g""#, Dialect::Powershell),
+            eq(&vec![
+                "a".to_string(),
+                "b c".into(),
+                "d'e".into(),
+                r#"f""g"#.into(),
+            ])
+        );
+    }
+
+    #[gtest]
+    fn elvish_quoting_rules() {
+        expect_that!(
+            args(r#"a 'b''c' "d\"e""#, Dialect::Elvish),
+            eq(&vec!["a".to_string(), "b'c".into(), "d\"e".into()])
+        );
+    }
+
+    #[gtest]
+    fn ignores_comments() {
+        expect_that!(
+            split("a b # c d", Dialect::Bash),
+            eq(&vec!["a".to_string(), "b".into()])
+        );
+    }
+
+    #[gtest]
+    fn quotes_only_when_needed() {
+        expect_that!(quote("main", Dialect::Bash, None), eq("main"));
+        expect_that!(quote("my file", Dialect::Bash, None), eq(r"my\ file"));
+        expect_that!(quote("$HOME*", Dialect::Zsh, None), eq(r"\$HOME\*"));
+        expect_that!(quote("my file", Dialect::Fish, None), eq(r"my\ file"));
+        expect_that!(quote("my file", Dialect::Nushell, None), eq("`my file`"));
+        expect_that!(quote("a`b c", Dialect::Nushell, None), eq(r#""a`b c""#));
+        expect_that!(quote("it's", Dialect::Powershell, None), eq("'it''s'"));
+        expect_that!(quote("it's", Dialect::Elvish, None), eq("'it''s'"));
+    }
+
+    #[gtest]
+    fn keeps_trailing_whitespace_unquoted() {
+        expect_that!(quote("my dir ", Dialect::Bash, None), eq(r"my\ dir "));
+    }
+
+    #[gtest]
+    fn quotes_with_open_quote() {
+        expect_that!(
+            quote("it's", Dialect::Bash, Some(Quote::Single)),
+            eq(r"'it'\''s'")
+        );
+        expect_that!(
+            quote("a\"$b", Dialect::Bash, Some(Quote::Double)),
+            eq(r#""a\"\$b""#)
+        );
+        expect_that!(
+            quote("it's", Dialect::Fish, Some(Quote::Single)),
+            eq(r"'it\'s'")
+        );
+        expect_that!(
+            quote("it's", Dialect::Nushell, Some(Quote::Single)),
+            eq(r#""it's""#)
+        );
+        expect_that!(
+            quote("a\"$b", Dialect::Powershell, Some(Quote::Double)),
+            eq(r#""a`"`$b""#)
+        );
+    }
+
+    #[gtest]
+    fn quoted_values_parse_back() {
+        let values = [
+            "plain",
+            "with space",
+            "it's \"quoted\"",
+            "$var*?",
+            "back\\slash",
+        ];
+        for dialect in [
+            Dialect::Bash,
+            Dialect::Zsh,
+            Dialect::Fish,
+            Dialect::Nushell,
+            Dialect::Powershell,
+            Dialect::Elvish,
+        ] {
+            for value in values {
+                let quoted = quote(value, dialect, None);
+                expect_that!(
+                    split(&quoted, dialect),
+                    eq(&vec![value.to_string()]),
+                    "dialect: {dialect:?}, quoted: {quoted}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use crate::complete::{complete_inline_value, locate_arg, ArgPosition};
+use crate::providers::Provider;
+use crate::types::{
+    CompleteParams, CompleteResult, CompletionValue, Diagnostic, Error, FilesHint, Severity,
+};
+use anyhow::Context;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Args)]
+pub struct SpecArgs {
+    /// The spec of the command.
+    file: PathBuf,
+    /// The format of the spec (by default usage for .kdl files, native
+    /// otherwise).
+    #[arg(long)]
+    format: Option<SpecFormat>,
+}
+
+/// The format of the spec file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpecFormat {
+    /// The native spec, as YAML, TOML or JSON (given by the extension).
+    Native,
+    /// A carapace-spec YAML file.
+    Carapace,
+    /// A usage KDL file.
+    Usage,
+}
+
+pub fn run_spec(args: SpecArgs) -> anyhow::Result<()> {
+    let spec = load_spec(&args.file, args.format)?;
+    crate::server::serve(|params| {
+        let mut result = spec.complete(params.clone())?;
+        crate::path::expand_files_hint(&mut result, &params);
+        Ok(result)
+    })
+}
+
+/// Loads the spec, in the given format (or the one given by the extension of
+/// the file).
+pub fn load_spec(path: &Path, format: Option<SpecFormat>) -> anyhow::Result<CommandSpec> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read spec {}", path.display()))?;
+    let extension = path.extension().and_then(|e| e.to_str());
+    let format = format.unwrap_or(match extension {
+        Some("kdl") => SpecFormat::Usage,
+        _ => SpecFormat::Native,
+    });
+    let spec = match (format, extension) {
+        (SpecFormat::Carapace, _) => crate::carapace_spec::parse(&content)?,
+        (SpecFormat::Usage, _) => crate::usage_spec::parse(&content)?,
+        (SpecFormat::Native, Some("yaml" | "yml")) => serde_yaml::from_str(&content)?,
+        (SpecFormat::Native, Some("toml")) => toml::from_str(&content)?,
+        (SpecFormat::Native, Some("json")) => serde_json::from_str(&content)?,
+        (SpecFormat::Native, _) => anyhow::bail!(
+            "unknown format of spec {}, expected .yaml, .toml or .json file",
+            path.display()
+        ),
+    };
+    Ok(spec)
+}
+
+/// A command (or subcommand) with its flags and args.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSpec {
+    /// The name of the command (not needed for the root command).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<FlagSpec>,
+    /// The positional args, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<ArgSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<CommandSpec>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FlagSpec {
+    /// All the names of the flag, e.g. `["-o", "--output"]`.
+    pub names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The value of the flag, for the flags that take one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<ValueSpec>,
+    /// Whether the value can only be given in the same arg (`--flag=value`),
+    /// so that the flag doesn't take the next arg.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional_value: bool,
+    /// Whether the flag is accepted by the subcommands too.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub persistent: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ArgSpec {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Whether the arg takes all the remaining positional args.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub variadic: bool,
+    #[serde(default)]
+    pub value: ValueSpec,
+}
+
+/// The sources of the values of a flag or an arg (all of them are used).
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ValueSpec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<ValueItem>,
+    /// Any files (or only the ones with the `extensions`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub files: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dirs: bool,
+    /// A shell command printing the values, one per line, optionally followed
+    /// by a tab and the description. It gets the args of the completed command
+    /// as `$0`, `$1`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// A built-in source of the values, e.g. `users` or `hosts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum ValueItem {
+    Value(String),
+    Described {
+        value: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    },
+}
+
+/// Completes the command with the spec from the file (used by the router for
+/// the commands configured with a spec).
+pub fn complete_file(
+    path: &Path,
+    format: Option<SpecFormat>,
+    params: CompleteParams,
+) -> Result<CompleteResult, Error> {
+    let spec = load_spec(path, format).map_err(|e| Error::internal(format!("{e:#}")))?;
+    spec.complete(params)
+}
+
+impl CommandSpec {
+    pub fn complete(&self, params: CompleteParams) -> Result<CompleteResult, Error> {
+        let Some((current, args)) = params.args.split_last() else {
+            return Err(Error::invalid_request(
+                "params.args is empty, required at least one element",
+            ));
+        };
+        if args.is_empty() {
+            return Ok(CompleteResult::default());
+        }
+
+        // The flags of the command, with the persistent flags of the parents.
+        let flags: Vec<&FlagSpec> = self.flags.iter().collect();
+        let ((command, flags), position) = locate_arg(
+            &params.args,
+            (self, flags),
+            |(_, flags), arg| {
+                let name = arg.split_once('=').map_or(arg, |(name, _)| name);
+                find_flag(flags, name)
+                    .filter(|f| f.value.is_some() && !f.optional_value && !arg.contains('='))
+            },
+            |(command, flags), arg| {
+                let sub = command.commands.iter().find(|c| c.matches(arg))?;
+                let mut flags: Vec<&FlagSpec> =
+                    flags.iter().copied().filter(|f| f.persistent).collect();
+                flags.extend(&sub.flags);
+                Some((sub, flags))
+            },
+        );
+        match position {
+            ArgPosition::FlagValue(flag) => {
+                let value = flag
+                    .value
+                    .as_ref()
+                    .expect("only flags with values are kept");
+                Ok(complete_value(value, current, &params))
+            }
+            ArgPosition::InlineFlagValue { name, prefix } => {
+                let Some(value) = find_flag(&flags, name).and_then(|f| f.value.as_ref()) else {
+                    return Ok(CompleteResult::default());
+                };
+                let result = complete_value(value, prefix, &params);
+                Ok(complete_inline_value(name, prefix, result, &params))
+            }
+            ArgPosition::Flag => Ok(CompleteResult {
+                values: flags
+                    .iter()
+                    .flat_map(|f| f.names.iter().map(move |name| (name, f)))
+                    .filter(|(name, _)| name.starts_with(current.as_str()))
+                    .map(|(name, f)| CompletionValue {
+                        value: name.clone(),
+                        description: f.description.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+            ArgPosition::Positional { index, subcommands } => {
+                let arg = command
+                    .args
+                    .get(index)
+                    .or_else(|| command.args.last().filter(|a| a.variadic));
+                let mut result = match arg {
+                    Some(arg) => complete_value(&arg.value, current, &params),
+                    None => CompleteResult::default(),
+                };
+                if subcommands {
+                    let subcommands = command
+                        .commands
+                        .iter()
+                        .filter(|c| c.name.starts_with(current.as_str()))
+                        .map(|c| CompletionValue {
+                            value: c.name.clone(),
+                            description: c.description.clone(),
+                            ..Default::default()
+                        });
+                    result.values.splice(0..0, subcommands);
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    fn matches(&self, arg: &str) -> bool {
+        self.name == arg || self.aliases.iter().any(|a| a == arg)
+    }
+}
+
+fn find_flag<'a>(flags: &[&'a FlagSpec], name: &str) -> Option<&'a FlagSpec> {
+    flags
+        .iter()
+        .find(|f| f.names.iter().any(|n| n == name))
+        .copied()
+}
+
+fn complete_value(value: &ValueSpec, prefix: &str, params: &CompleteParams) -> CompleteResult {
+    let mut result = CompleteResult::default();
+    for item in &value.values {
+        let (value, description) = match item {
+            ValueItem::Value(value) => (value, None),
+            ValueItem::Described { value, description } => (value, description.clone()),
+        };
+        if value.starts_with(prefix) {
+            result.values.push(CompletionValue {
+                value: value.clone(),
+                description,
+                ..Default::default()
+            });
+        }
+    }
+    if let Some(provider) = value.provider {
+        let values = provider.values(params);
+        result
+            .values
+            .extend(values.into_iter().filter(|v| v.value.starts_with(prefix)));
+    }
+    if let Some(command) = &value.command {
+        match run_value_command(command, params) {
+            Ok(values) => result
+                .values
+                .extend(values.into_iter().filter(|v| v.value.starts_with(prefix))),
+            Err(message) => result.diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                message,
+            }),
+        }
+    }
+    if value.files || value.dirs || !value.extensions.is_empty() {
+        result.files = Some(FilesHint {
+            extensions: value.extensions.clone(),
+            directories_only: value.dirs && !value.files && value.extensions.is_empty(),
+            ..Default::default()
+        });
+    }
+    result
+}
+
+fn run_value_command(
+    command: &str,
+    params: &CompleteParams,
+) -> Result<Vec<CompletionValue>, String> {
+    let mut cmd = std::process::Command::new("sh");
+    cmd.arg("-c").arg(command);
+    // The current arg is not passed, as the values are filtered anyway.
+    cmd.args(&params.args[..params.args.len() - 1]);
+    params.apply_to(&mut cmd);
+    let output = cmd
+        .output()
+        .map_err(|e| format!("failed to run {command:?}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{command:?} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('\t') {
+            Some((value, description)) => CompletionValue {
+                value: value.to_string(),
+                description: Some(description.to_string()).filter(|d| !d.is_empty()),
+                ..Default::default()
+            },
+            None => CompletionValue {
+                value: line.to_string(),
+                ..Default::default()
+            },
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{complete_spec, values};
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn loads_all_formats() {
+        let yaml = load_spec(Path::new("testdata/spec/mytool.yaml"), None).unwrap();
+        let toml = load_spec(Path::new("testdata/spec/mytool.toml"), None).unwrap();
+        let json = load_spec(Path::new("testdata/spec/mytool.json"), None).unwrap();
+        expect_that!(toml, eq(&yaml));
+        expect_that!(json, eq(&yaml));
+    }
+
+    #[gtest]
+    fn completes_with_spec() {
+        let spec = load_spec(Path::new("testdata/spec/mytool.yaml"), None).unwrap();
+
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", ""]).values),
+            elements_are![eq("build"), eq("deploy")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "-"]).values),
+            elements_are![eq("-v"), eq("--verbose"), eq("--config")]
+        );
+        let result = complete_spec(&spec, &["mytool", "--config", ""]);
+        expect_that!(
+            result.files,
+            some(eq(&FilesHint {
+                extensions: vec!["toml".into()],
+                ..Default::default()
+            }))
+        );
+        // The persistent flags are inherited, the args are matched in order.
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "-v", "b", "--"]).values),
+            elements_are![eq("--verbose"), eq("--release"), eq("--target")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "b", "--target", "l"]).values),
+            elements_are![eq("linux")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "build", "--target=w"]).values),
+            elements_are![eq("--target=windows")]
+        );
+        let result = complete_spec(&spec, &["mytool", "deploy", ""]);
+        expect_that!(
+            values(&result.values),
+            elements_are![eq("prod"), eq("staging")]
+        );
+        expect_that!(
+            result.values[0].description.as_deref(),
+            some(eq("deploy prod from mytool"))
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "deploy", "--signal", "T"]).values),
+            elements_are![eq("TRAP"), eq("TERM"), eq("TSTP"), eq("TTIN"), eq("TTOU")]
+        );
+        let result = complete_spec(&spec, &["mytool", "deploy", "prod", "x"]);
+        expect_that!(
+            result.files,
+            some(eq(&FilesHint {
+                directories_only: true,
+                ..Default::default()
+            }))
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! The helpers shared by the tests.
+
+use crate::types::CompletionValue;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A temporary directory, unique to the test and deleted when dropped (also
+/// when the test fails).
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static DIRS: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "cap-{name}-{}-{}",
+            std::process::id(),
+            DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        // Left by a previous process with the same pid, that was killed.
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+pub fn values(values: &[CompletionValue]) -> Vec<String> {
+    values.iter().map(|v| v.value.clone()).collect()
+}
+
+/// The values with their descriptions.
+pub fn pairs(values: Vec<CompletionValue>) -> Vec<(String, String)> {
+    values
+        .into_iter()
+        .map(|v| (v.value, v.description.unwrap_or_default()))
+        .collect()
+}
+
+pub fn pair(value: &str, description: &str) -> (String, String) {
+    (value.to_string(), description.to_string())
+}
+
+/// Completes the args with the spec.
+#[cfg(feature = "cli")]
+pub fn complete_spec(
+    spec: &crate::spec::CommandSpec,
+    args: &[&str],
+) -> crate::types::CompleteResult {
+    spec.complete(crate::types::CompleteParams {
+        args: args.iter().map(|a| a.to_string()).collect(),
+        ..Default::default()
+    })
+    .unwrap()
+}
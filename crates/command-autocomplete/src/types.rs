@@ -1,10 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Message {
     Request(Request),
     Response(Response),
+    Notification(Notification),
 }
 
 impl From<Response> for Message {
@@ -19,6 +21,12 @@ impl From<Request> for Message {
     }
 }
 
+impl From<Notification> for Message {
+    fn from(value: Notification) -> Self {
+        Message::Notification(value)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Request {
@@ -40,6 +48,23 @@ impl Request {
     }
 }
 
+/// A message that is not responded to (unlike the [Request]).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Notification {
+    pub method: String,
+    pub params: serde_json::Value,
+}
+
+impl Notification {
+    pub fn new(method: impl Into<String>, params: impl Serialize) -> Self {
+        Notification {
+            method: method.into(),
+            params: serde_json::to_value(params).unwrap(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged, deny_unknown_fields)]
 pub enum Response {
@@ -96,21 +121,202 @@ impl Response {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct CompleteParams {
     pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub envs: Vec<EnvironmentVariable>,
+    /// When set, the cached results should not be used (e.g. when the user
+    /// explicitly asks for the fresh values).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub refresh: bool,
+    /// The maximal number of the values the client wants (e.g. to show them
+    /// to the user), all of them by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<usize>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl CompleteParams {
+    /// Configures the command to run in the working directory and with the
+    /// environment of the completed command.
+    pub fn apply_to(&self, command: &mut std::process::Command) {
+        if let Some(working_dir) = &self.working_dir {
+            command.current_dir(working_dir);
+        }
+        if !self.envs.is_empty() {
+            command.env_clear();
+            command.envs(self.envs.iter().map(|e| (&e.name, &e.value)));
+        }
+    }
+
+    /// The environment variable of the completed command (or of this process,
+    /// when the request has no environment).
+    pub fn env(&self, name: &str) -> Option<String> {
+        if self.envs.is_empty() {
+            return std::env::var(name).ok();
+        }
+        self.envs
+            .iter()
+            .find(|e| e.name == name)
+            .map(|e| e.value.clone())
+    }
+
+    /// Finds the program in the PATH of the completed command (or relative to
+    /// its working directory, when it contains a slash).
+    pub fn find_program(&self, program: &str) -> Option<PathBuf> {
+        let working_dir = self
+            .working_dir
+            .clone()
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        if program.contains('/') {
+            return Some(working_dir.join(program)).filter(|p| p.is_file());
+        }
+        let path = match self.envs.iter().find(|e| e.name == "PATH") {
+            Some(env) => env.value.clone(),
+            None => std::env::var("PATH").unwrap_or_default(),
+        };
+        path.split(':')
+            .map(|dir| working_dir.join(dir).join(program))
+            .find(|p| p.is_file())
+    }
+}
+
+/// The value inserted by the user, out of the values completed for the args.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AcceptedParams {
+    pub args: Vec<String>,
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EnvironmentVariable {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct CompleteResult {
     pub values: Vec<CompletionValue>,
+    /// The completer that provided the values (set by the router).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completer: Option<String>,
+    /// The usage of the command (or flag) being completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<String>,
+    /// Problems found in the completed command (e.g. unknown flag).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// When set, the values are in a meaningful order that should not be
+    /// changed (e.g. by sorting them).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keep_order: bool,
+    /// When set, the paths matching the hint should be completed in addition to
+    /// the values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<FilesHint>,
+    /// When set, the values are already filtered by the completed word, so
+    /// the router doesn't filter them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub filtered: bool,
+    /// When set, the result can be reused for the next completions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheControl>,
+    /// When set, the values are not all the values (e.g. they were trimmed to
+    /// the `max_results`), so the completion should be requested again when
+    /// the word changes, instead of filtering these values.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_incomplete: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Describes for how long and for which requests the result can be reused.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CacheControl {
+    /// The number of seconds the result stays valid.
+    pub ttl: u64,
+    /// The number of the leading args the result depends on (all of them by
+    /// default). The values of the result should not be filtered by the other
+    /// args, as it's reused when they change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<usize>,
+    /// When set, the result doesn't depend on the working directory.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub any_working_dir: bool,
+    /// The environment variables the result depends on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub envs: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FilesHint {
+    /// Only the files with one of these extensions (without the dot), when not
+    /// empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    /// Only the directories.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub directories_only: bool,
+    /// The directory in which the paths are completed, relative to the working
+    /// directory (defaults to the working directory).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct CompletionValue {
     pub value: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// The text shown instead of the value when listing the completions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+    /// The group of the value (e.g. "local branches").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Space separated style attributes, e.g. "bold bright-blue bg-black".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+    /// When set, no space should be inserted after the value.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_space: bool,
+    /// The kind of the value, for the values that are paths.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ValueKind>,
+    /// The parts of the value matched by the completed word, for highlighting.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matches: Vec<MatchRange>,
+}
+
+/// The range of bytes in the value, `end` exclusive.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MatchRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueKind {
+    File,
+    Directory,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -0,0 +1,183 @@
+//! Importing the [usage](https://usage.jdx.dev) specs (KDL) into the
+//! [CommandSpec].
+//!
+//! The `cmd`, `flag`, `arg`, `alias`, `choices` and `complete` (with `run`)
+//! nodes are supported. The args and flag values named `file` or `path` are
+//! completed with files, the ones named `dir` with directories. The hidden
+//! commands, flags and args are skipped.
+
+use crate::kdl::{self, Node, Value};
+use crate::spec::{ArgSpec, CommandSpec, FlagSpec, ValueItem, ValueSpec};
+use std::collections::HashMap;
+
+pub fn parse(content: &str) -> anyhow::Result<CommandSpec> {
+    let nodes = kdl::parse(content)?;
+    let mut spec = CommandSpec::default();
+    let name = ["bin", "name"]
+        .iter()
+        .find_map(|name| nodes.iter().find(|n| n.name == *name)?.arg(0));
+    if let Some(name) = name {
+        spec.name = name.to_string();
+    }
+    fill_command(&mut spec, &nodes, &HashMap::new());
+    Ok(spec)
+}
+
+/// Fills the spec with the child nodes of the command. The `completes` are
+/// the commands completing the values by their name, defined by the parents.
+fn fill_command(spec: &mut CommandSpec, nodes: &[Node], completes: &HashMap<String, String>) {
+    let mut completes = completes.clone();
+    for node in nodes.iter().filter(|n| n.name == "complete") {
+        if let (Some(name), Some(run)) = (node.arg(0), node.prop_str("run")) {
+            completes.insert(name.to_string(), run.to_string());
+        }
+    }
+    for node in nodes {
+        if node.prop_bool("hide") {
+            continue;
+        }
+        match node.name.as_str() {
+            "alias" => spec
+                .aliases
+                .extend(node.args.iter().filter_map(Value::as_str).map(String::from)),
+            "about" | "help" if spec.description.is_none() => {
+                spec.description = node.arg(0).map(String::from)
+            }
+            "flag" => spec.flags.extend(flag(node, &completes)),
+            "arg" => spec.args.extend(arg(node, &completes)),
+            "cmd" => {
+                let Some(name) = node.arg(0) else {
+                    continue;
+                };
+                let mut command = CommandSpec {
+                    name: name.to_string(),
+                    description: help(node),
+                    ..Default::default()
+                };
+                fill_command(&mut command, &node.children, &completes);
+                spec.commands.push(command);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Converts e.g. `flag "-o --output <path>" help="..." global=#true`.
+fn flag(node: &Node, completes: &HashMap<String, String>) -> Option<FlagSpec> {
+    let mut names = vec![];
+    let mut value_name = None;
+    for token in node.arg(0)?.split_whitespace() {
+        if token.starts_with(['<', '[']) {
+            value_name = Some(arg_name(token));
+        } else {
+            names.push(token.trim_end_matches("...").to_string());
+        }
+    }
+    names.extend(node.prop_str("negate").map(String::from));
+    // The value can be also described by the arg child node.
+    let arg = node.children.iter().find(|n| n.name == "arg");
+    let value_name = value_name.or_else(|| Some(arg_name(arg?.arg(0)?)));
+    Some(FlagSpec {
+        names,
+        description: help(node),
+        value: value_name.map(|name| {
+            let nodes: Vec<&Node> = std::iter::once(node).chain(arg).collect();
+            value_spec(&name, &nodes, completes)
+        }),
+        persistent: node.prop_bool("global"),
+        ..Default::default()
+    })
+}
+
+/// Converts e.g. `arg "[files]..." help="..."`.
+fn arg(node: &Node, completes: &HashMap<String, String>) -> Option<ArgSpec> {
+    let usage = node.arg(0)?;
+    let name = arg_name(usage);
+    Some(ArgSpec {
+        description: help(node),
+        variadic: usage.ends_with("...") || node.prop_bool("var"),
+        value: value_spec(&name, &[node], completes),
+        name,
+    })
+}
+
+/// The name of the arg, e.g. `files` for `[files]...`.
+fn arg_name(usage: &str) -> String {
+    usage
+        .trim_end_matches("...")
+        .trim_matches(['<', '>', '[', ']'])
+        .to_string()
+}
+
+fn help(node: &Node) -> Option<String> {
+    node.prop_str("help")
+        .or_else(|| node.children.iter().find(|n| n.name == "help")?.arg(0))
+        .map(String::from)
+}
+
+/// The values of the arg or flag with the name, from the `choices` children
+/// of the nodes, the `complete` command or the name itself.
+fn value_spec(name: &str, nodes: &[&Node], completes: &HashMap<String, String>) -> ValueSpec {
+    let mut value = ValueSpec {
+        values: nodes
+            .iter()
+            .flat_map(|n| &n.children)
+            .filter(|n| n.name == "choices")
+            .flat_map(|n| n.args.iter().filter_map(Value::as_str))
+            .map(|choice| ValueItem::Value(choice.to_string()))
+            .collect(),
+        command: completes.get(name).cloned(),
+        ..Default::default()
+    };
+    if value.values.is_empty() && value.command.is_none() {
+        match name {
+            "file" | "files" | "path" | "paths" => value.files = true,
+            "dir" | "dirs" | "directory" => value.dirs = true,
+            _ => {}
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{complete_spec, values};
+    use crate::types::FilesHint;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn imports_usage_spec() {
+        let content = std::fs::read_to_string("testdata/spec/mytool.usage.kdl").unwrap();
+        let spec = parse(&content).unwrap();
+
+        expect_that!(spec.name, eq("mytool"));
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", ""]).values),
+            elements_are![eq("build"), eq("deploy")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "-v", "b", "--"]).values),
+            elements_are![eq("--verbose"), eq("--release"), eq("--target")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "b", "--target", ""]).values),
+            elements_are![eq("linux"), eq("windows")]
+        );
+        expect_that!(
+            values(&complete_spec(&spec, &["mytool", "deploy", ""]).values),
+            elements_are![eq("prod"), eq("staging")]
+        );
+        expect_that!(
+            complete_spec(&spec, &["mytool", "deploy", "prod", "x", ""]).files,
+            some(eq(&FilesHint {
+                directories_only: true,
+                ..Default::default()
+            }))
+        );
+        expect_that!(
+            complete_spec(&spec, &["mytool", "--config", ""]).files,
+            some(eq(&FilesHint::default()))
+        );
+    }
+}